@@ -1 +1,3 @@
+pub(crate) mod schema;
 pub mod v1alpha1;
+pub mod v1beta1;
@@ -0,0 +1,77 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde_json::json;
+
+/// Regex for keys of Secrets and ConfigMaps
+pub(crate) const KEY_PATTERN: &str = "^[-._a-zA-Z0-9]+$";
+/// Regex for names of Kubernetes objects (RFC 1123 subdomain)
+pub(crate) const OBJECT_NAME_PATTERN: &str =
+    "^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*$";
+/// Regex for durations, like 30s, 5m or 1h30m. At least one of the parts
+/// must be non-zero, because zero intervals are rejected by validation too
+pub(crate) const DURATION_PATTERN: &str = "^([0-9]+(s|m|h))*0*[1-9][0-9]*(s|m|h)([0-9]+(s|m|h))*$";
+/// Lists must be bounded, otherwise the API server can't estimate the cost of CEL rules
+pub(crate) const MAX_TARGETS: u32 = 64;
+pub(crate) const MAX_INPUTS: u32 = 256;
+pub(crate) const MAX_TEMPLATES: u32 = 256;
+
+/// Schema of a list that is merged by keys during server-side apply,
+/// the API server also makes sure the keys are unique
+pub(crate) fn list_map_schema<T: JsonSchema>(
+    gen: &mut SchemaGenerator,
+    description: &str,
+    keys: &[&str],
+    max_items: u32,
+) -> Schema {
+    let mut schema = gen.subschema_for::<Vec<T>>().into_object();
+    schema.metadata().description = Some(description.to_string());
+    schema.array().max_items = Some(max_items);
+    schema
+        .extensions
+        .insert("x-kubernetes-list-type".to_string(), json!("map"));
+    schema
+        .extensions
+        .insert("x-kubernetes-list-map-keys".to_string(), json!(keys));
+    Schema::Object(schema)
+}
+
+/// Schema of the interval after which an object is reconciled again
+pub(crate) fn refresh_interval_schema() -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        ..Default::default()
+    };
+    schema.metadata().description = Some(
+        "Reconcile the object again after the interval, even if nothing has changed, e.g. 10m"
+            .to_string(),
+    );
+    schema.string().pattern = Some(DURATION_PATTERN.to_string());
+    Schema::Object(schema)
+}
+
+/// Check if the string can be used as a key in Secrets and ConfigMaps
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= 253
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn zero_durations_do_not_match() {
+        let pattern = Regex::new(DURATION_PATTERN).unwrap();
+        for duration in ["30s", "5m", "1h30m", "1h0m", "010m"] {
+            assert!(pattern.is_match(duration), "{}", duration);
+        }
+        for duration in ["0s", "00m", "0h0m0s", "", "5", "m"] {
+            assert!(!pattern.is_match(duration), "{}", duration);
+        }
+    }
+}
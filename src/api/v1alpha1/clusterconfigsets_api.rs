@@ -0,0 +1,419 @@
+use crate::api::schema::{
+    list_map_schema, refresh_interval_schema, KEY_PATTERN, MAX_INPUTS, MAX_TARGETS, MAX_TEMPLATES,
+    OBJECT_NAME_PATTERN,
+};
+use crate::api::v1alpha1::configsets_api::{
+    ConfigSetSpec, Input, InputWithName, Kinds, Target, TargetWithName, Templates, ValidationError,
+    ValidationErrors,
+};
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, LabelSelector};
+use kube::CustomResource;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// ClusterConfigSet is a cluster-scoped ConfigSet.
+/// Inputs are taken from Secrets and ConfigMaps in explicitly set
+/// namespaces, and targets are created in every namespace that
+/// matches the target's namespace selector
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[cfg_attr(test, derive(Default))]
+#[kube(
+    kind = "ClusterConfigSet",
+    group = "shoebill.badhouseplants.net",
+    version = "v1alpha1"
+)]
+#[kube(
+    status = "ClusterConfigSetStatus",
+    shortname = "cconfset",
+    category = "shoebill"
+)]
+#[kube(
+    printcolumn = r#"{"name":"Ready","type":"boolean","jsonPath":".status.ready"}"#,
+    printcolumn = r#"{"name":"Reason","type":"string","jsonPath":".status.reason"}"#,
+    printcolumn = r#"{"name":"Targets","type":"integer","jsonPath":".status.targets"}"#,
+    printcolumn = r#"{"name":"Last Sync","type":"date","jsonPath":".status.lastSyncTime"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterConfigSetSpec {
+    pub targets: Vec<ClusterTargetWithName>,
+    pub inputs: Vec<ClusterInputWithName>,
+    pub templates: Vec<Templates>,
+    /// Reconcile the ClusterConfigSet again after the interval, e.g. 10m
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_interval: Option<String>,
+}
+
+// The schema is written by hand for the same reason as for ConfigSets
+impl JsonSchema for ClusterConfigSetSpec {
+    fn schema_name() -> String {
+        "ClusterConfigSetSpec".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            ..Default::default()
+        };
+        let object = schema.object();
+        object.properties.insert(
+            "targets".to_string(),
+            list_map_schema::<ClusterTargetWithName>(
+                gen,
+                "Secrets and ConfigMaps that should be populated with rendered templates in every selected namespace",
+                &["name"],
+                MAX_TARGETS,
+            ),
+        );
+        object.properties.insert(
+            "inputs".to_string(),
+            list_map_schema::<ClusterInputWithName>(
+                gen,
+                "Values from Secrets and ConfigMaps that can be used in templates",
+                &["name"],
+                MAX_INPUTS,
+            ),
+        );
+        object.properties.insert(
+            "templates".to_string(),
+            list_map_schema::<Templates>(
+                gen,
+                "Handlebars templates that are rendered with inputs and written to targets",
+                &["target", "name"],
+                MAX_TEMPLATES,
+            ),
+        );
+        object
+            .properties
+            .insert("refreshInterval".to_string(), refresh_interval_schema());
+        object.required = BTreeSet::from([
+            "targets".to_string(),
+            "inputs".to_string(),
+            "templates".to_string(),
+        ]);
+        schema.extensions.insert(
+            "x-kubernetes-validations".to_string(),
+            json!([{
+                "rule": "self.templates.all(t, self.targets.exists(x, x.name == t.target))",
+                "message": "every template must refer to a target defined in targets",
+            }]),
+        );
+        Schema::Object(schema)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterConfigSetStatus {
+    /// Whether all the targets are populated with rendered templates
+    pub ready: bool,
+    /// Short reason of the current state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Human-readable details, set when the reconciliation fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Number of target objects managed by the ClusterConfigSet in all namespaces
+    #[serde(default)]
+    pub targets: i64,
+    /// Namespaces where targets are managed, they are cleaned up
+    /// when namespaces stop matching selectors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<String>,
+    /// Last time the targets were synced successfully
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync_time: Option<DateTime<Utc>>,
+    /// Generation of the ClusterConfigSet that was reconciled last
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    /// Value of the reconcile-at annotation that was handled last
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_handled_reconcile_at: Option<String>,
+    /// Changes that would be written to targets, set when the controller runs in dry-run mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_changes: Vec<String>,
+    /// Latest observations of the ClusterConfigSet state
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterTargetWithName {
+    /// Name of the target that is used by templates
+    #[schemars(length(min = 1, max = 253))]
+    pub name: String,
+    pub target: Target,
+    /// Target is created in every namespace matching the selector
+    pub namespace_selector: LabelSelector,
+}
+
+impl ClusterTargetWithName {
+    /// The namespace selector in the format of label selectors of list requests
+    pub fn selector_string(&self) -> Result<String, ValidationError> {
+        let invalid = |reason: String| ValidationError::InvalidSelector {
+            target: self.name.clone(),
+            reason,
+        };
+        let mut requirements: Vec<String> = vec![];
+        if let Some(labels) = &self.namespace_selector.match_labels {
+            for (key, value) in labels {
+                requirements.push(format!("{}={}", key, value));
+            }
+        }
+        if let Some(expressions) = &self.namespace_selector.match_expressions {
+            for expr in expressions {
+                let values = expr.values.clone().unwrap_or_default();
+                match expr.operator.as_str() {
+                    "In" | "NotIn" if values.is_empty() => {
+                        return Err(invalid(format!(
+                            "operator {} on {} requires values",
+                            expr.operator, expr.key
+                        )))
+                    }
+                    "Exists" | "DoesNotExist" if !values.is_empty() => {
+                        return Err(invalid(format!(
+                            "operator {} on {} doesn't take values",
+                            expr.operator, expr.key
+                        )))
+                    }
+                    "In" => requirements.push(format!("{} in ({})", expr.key, values.join(","))),
+                    "NotIn" => {
+                        requirements.push(format!("{} notin ({})", expr.key, values.join(",")))
+                    }
+                    "Exists" => requirements.push(expr.key.clone()),
+                    "DoesNotExist" => requirements.push(format!("!{}", expr.key)),
+                    operator => return Err(invalid(format!("unknown operator {}", operator))),
+                }
+            }
+        }
+        Ok(requirements.join(","))
+    }
+
+    /// Whether a namespace with the labels matches the namespace selector,
+    /// the selector is checked the same way as for list requests
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> Result<bool, ValidationError> {
+        self.selector_string()?;
+        let by_labels = self
+            .namespace_selector
+            .match_labels
+            .iter()
+            .flatten()
+            .all(|(key, value)| labels.get(key) == Some(value));
+        let by_expressions = self
+            .namespace_selector
+            .match_expressions
+            .iter()
+            .flatten()
+            .all(|expr| {
+                let values = expr.values.clone().unwrap_or_default();
+                match expr.operator.as_str() {
+                    "In" => labels.get(&expr.key).is_some_and(|v| values.contains(v)),
+                    "NotIn" => !labels.get(&expr.key).is_some_and(|v| values.contains(v)),
+                    "Exists" => labels.contains_key(&expr.key),
+                    _ => !labels.contains_key(&expr.key),
+                }
+            });
+        Ok(by_labels && by_expressions)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterInputWithName {
+    /// Name of the variable that can be used in templates
+    #[schemars(length(min = 1, max = 253))]
+    pub name: String,
+    pub from: ClusterInput,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterInput {
+    pub kind: Kinds,
+    /// Namespace of the Secret or ConfigMap
+    #[schemars(length(min = 1, max = 63), regex = "OBJECT_NAME_PATTERN")]
+    pub namespace: String,
+    /// Name of the Secret or ConfigMap
+    #[schemars(length(min = 1, max = 253), regex = "OBJECT_NAME_PATTERN")]
+    pub name: String,
+    /// Key of the value in the Secret or ConfigMap
+    #[schemars(length(min = 1, max = 253), regex = "KEY_PATTERN")]
+    pub key: String,
+}
+
+impl ClusterInput {
+    /// Input without the namespace, as it's used by ConfigSets
+    pub fn to_input(&self) -> Input {
+        Input {
+            kind: self.kind.clone(),
+            name: self.name.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl ClusterConfigSetSpec {
+    /// Rules for targets, inputs and templates are the same as for ConfigSets,
+    /// so the namespaced spec is validated, and namespace selectors are checked on top
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = match self.to_configset_spec().validate() {
+            Ok(()) => vec![],
+            Err(ValidationErrors(errors)) => errors,
+        };
+        for target in self.targets.iter() {
+            if let Err(err) = target.selector_string() {
+                errors.push(err);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    /// The spec that is rendered in every namespace
+    pub fn to_configset_spec(&self) -> ConfigSetSpec {
+        ConfigSetSpec {
+            targets: self
+                .targets
+                .iter()
+                .map(|target| TargetWithName {
+                    name: target.name.clone(),
+                    target: target.target.clone(),
+                })
+                .collect(),
+            inputs: self
+                .inputs
+                .iter()
+                .map(|input| InputWithName {
+                    name: input.name.clone(),
+                    from: input.from.to_input(),
+                })
+                .collect(),
+            templates: self.templates.clone(),
+            refresh_interval: self.refresh_interval.clone(),
+        }
+    }
+
+    /// Interval after which the ClusterConfigSet is reconciled again, if it's set and valid
+    pub fn refresh_duration(&self) -> Option<Duration> {
+        self.to_configset_spec().refresh_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelectorRequirement;
+
+    fn target(expressions: Vec<(&str, &str, Vec<&str>)>) -> ClusterTargetWithName {
+        ClusterTargetWithName {
+            name: "app".to_string(),
+            target: Target {
+                kind: Kinds::Secret,
+                name: "app".to_string(),
+            },
+            namespace_selector: LabelSelector {
+                match_labels: Some(BTreeMap::from([("team".to_string(), "a".to_string())])),
+                match_expressions: Some(
+                    expressions
+                        .into_iter()
+                        .map(|(key, operator, values)| LabelSelectorRequirement {
+                            key: key.to_string(),
+                            operator: operator.to_string(),
+                            values: Some(values.into_iter().map(String::from).collect()),
+                        })
+                        .collect(),
+                ),
+            },
+        }
+    }
+
+    #[test]
+    fn selectors_are_converted() {
+        let target = target(vec![
+            ("env", "In", vec!["dev", "prod"]),
+            ("tier", "NotIn", vec!["db"]),
+            ("app", "Exists", vec![]),
+            ("legacy", "DoesNotExist", vec![]),
+        ]);
+        assert_eq!(
+            target.selector_string().unwrap(),
+            "team=a,env in (dev,prod),tier notin (db),app,!legacy"
+        );
+    }
+
+    #[test]
+    fn unknown_operators_are_rejected() {
+        let err = target(vec![("env", "Matches", vec!["dev"])])
+            .selector_string()
+            .unwrap_err();
+        assert!(matches!(err, ValidationError::InvalidSelector { .. }));
+    }
+
+    #[test]
+    fn empty_values_are_rejected() {
+        for operator in ["In", "NotIn"] {
+            assert!(target(vec![("env", operator, vec![])])
+                .selector_string()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_selectors_fail_validation() {
+        let spec = ClusterConfigSetSpec {
+            targets: vec![target(vec![("env", "Matches", vec!["dev"])])],
+            ..Default::default()
+        };
+        let errors = spec.validate().unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert!(matches!(
+            errors.0[0],
+            ValidationError::InvalidSelector { .. }
+        ));
+    }
+
+    #[test]
+    fn namespaces_are_matched_by_labels() {
+        let labels = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let target = target(vec![
+            ("env", "In", vec!["dev", "prod"]),
+            ("tier", "NotIn", vec!["db"]),
+            ("app", "Exists", vec![]),
+            ("legacy", "DoesNotExist", vec![]),
+        ]);
+        let matching = labels(&[("team", "a"), ("env", "dev"), ("app", "web")]);
+        assert!(target.matches(&matching).unwrap());
+        for (key, value) in [
+            ("team", "b"),
+            ("env", "test"),
+            ("tier", "db"),
+            ("legacy", "true"),
+        ] {
+            let mut labels = matching.clone();
+            labels.insert(key.to_string(), value.to_string());
+            assert!(!target.matches(&labels).unwrap(), "{key}={value}");
+        }
+        let mut without_app = matching.clone();
+        without_app.remove("app");
+        assert!(!target.matches(&without_app).unwrap());
+    }
+
+    #[test]
+    fn invalid_selectors_match_nothing() {
+        let target = target(vec![("env", "Matches", vec!["dev"])]);
+        assert!(target.matches(&BTreeMap::new()).is_err());
+    }
+}
@@ -1,13 +1,19 @@
-use futures::StreamExt;
-use kube::api::ListParams;
-use kube::runtime::controller::Action;
-use kube::runtime::watcher::Config;
-use kube::runtime::Controller;
-use kube::{Api, Client, CustomResource};
-use log::*;
+use crate::api::schema::{
+    is_valid_key, list_map_schema, refresh_interval_schema, KEY_PATTERN, MAX_INPUTS, MAX_TARGETS,
+    MAX_TEMPLATES, OBJECT_NAME_PATTERN,
+};
+use chrono::{DateTime, Utc};
+use core::fmt;
+use handlebars::Template;
+use humantime::parse_duration;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+use kube::CustomResource;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use serde_json::json;
+use std::collections::{BTreeSet, HashSet};
 use std::time::Duration;
 use thiserror::Error;
 
@@ -16,7 +22,7 @@ use thiserror::Error;
 /// from Secrets and ConfigMaps defined in inputs, use them for
 /// building new variables, that are defined in templates, and
 /// put them to target Secrets or ConfigMaps
-#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, PartialEq)]
 #[cfg_attr(test, derive(Default))]
 #[kube(
     kind = "ConfigSet",
@@ -24,52 +30,271 @@ use thiserror::Error;
     version = "v1alpha1",
     namespaced
 )]
-#[kube(status = "ConfigSetStatus", shortname = "confset")]
+#[kube(
+    status = "ConfigSetStatus",
+    shortname = "confset",
+    category = "shoebill"
+)]
+#[kube(
+    printcolumn = r#"{"name":"Ready","type":"boolean","jsonPath":".status.ready"}"#,
+    printcolumn = r#"{"name":"Reason","type":"string","jsonPath":".status.reason"}"#,
+    printcolumn = r#"{"name":"Targets","type":"integer","jsonPath":".status.targets"}"#,
+    printcolumn = r#"{"name":"Last Sync","type":"date","jsonPath":".status.lastSyncTime"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
 pub struct ConfigSetSpec {
     pub targets: Vec<TargetWithName>,
     pub inputs: Vec<InputWithName>,
     pub templates: Vec<Templates>,
+    /// Reconcile the ConfigSet again after the interval, e.g. 10m
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_interval: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+// The schema is written by hand, because validation rules that
+// refer to more than one field must be set on the spec itself,
+// and schemars can't extend the derived schema of a struct
+impl JsonSchema for ConfigSetSpec {
+    fn schema_name() -> String {
+        "ConfigSetSpec".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            ..Default::default()
+        };
+        let object = schema.object();
+        object.properties.insert(
+            "targets".to_string(),
+            list_map_schema::<TargetWithName>(
+                gen,
+                "Secrets and ConfigMaps that should be populated with rendered templates",
+                &["name"],
+                MAX_TARGETS,
+            ),
+        );
+        object.properties.insert(
+            "inputs".to_string(),
+            list_map_schema::<InputWithName>(
+                gen,
+                "Values from Secrets and ConfigMaps that can be used in templates",
+                &["name"],
+                MAX_INPUTS,
+            ),
+        );
+        object.properties.insert(
+            "templates".to_string(),
+            list_map_schema::<Templates>(
+                gen,
+                "Handlebars templates that are rendered with inputs and written to targets",
+                &["target", "name"],
+                MAX_TEMPLATES,
+            ),
+        );
+        object
+            .properties
+            .insert("refreshInterval".to_string(), refresh_interval_schema());
+        object.required = BTreeSet::from([
+            "targets".to_string(),
+            "inputs".to_string(),
+            "templates".to_string(),
+        ]);
+        schema.extensions.insert(
+            "x-kubernetes-validations".to_string(),
+            json!([{
+                "rule": "self.templates.all(t, self.targets.exists(x, x.name == t.target))",
+                "message": "every template must refer to a target defined in targets",
+            }]),
+        );
+        Schema::Object(schema)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ConfigSetStatus {
-    ready: bool,
+    /// Whether all the targets are populated with rendered templates
+    pub ready: bool,
+    /// Short reason of the current state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Human-readable details, set when the reconciliation fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Number of targets managed by the ConfigSet
+    #[serde(default)]
+    pub targets: i64,
+    /// Last time the targets were synced successfully
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync_time: Option<DateTime<Utc>>,
+    /// Generation of the ConfigSet that was reconciled last
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    /// Value of the reconcile-at annotation that was handled last
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_handled_reconcile_at: Option<String>,
+    /// Changes that would be written to targets, set when the controller runs in dry-run mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_changes: Vec<String>,
+    /// Latest observations of the ConfigSet state
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct TargetWithName {
+    /// Name of the target that is used by templates
+    #[schemars(length(min = 1, max = 253))]
     pub name: String,
     pub target: Target,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Target {
     pub kind: Kinds,
+    /// Name of the Secret or ConfigMap, it's created if it doesn't exist
+    #[schemars(length(min = 1, max = 253), regex = "OBJECT_NAME_PATTERN")]
     pub name: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InputWithName {
+    /// Name of the variable that can be used in templates
+    #[schemars(length(min = 1, max = 253))]
     pub name: String,
     pub from: Input,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+/// Kind of the object that is used as an input or a target,
+/// either a Secret or a ConfigMap
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum Kinds {
+    /// core/v1 Secret
     Secret,
+    /// core/v1 ConfigMap
     ConfigMap,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Input {
     pub kind: Kinds,
+    /// Name of the Secret or ConfigMap in the namespace of the ConfigSet
+    #[schemars(length(min = 1, max = 253), regex = "OBJECT_NAME_PATTERN")]
     pub name: String,
+    /// Key of the value in the Secret or ConfigMap
+    #[schemars(length(min = 1, max = 253), regex = "KEY_PATTERN")]
     pub key: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Templates {
+    /// Key that the rendered template is written to
+    #[schemars(length(min = 1, max = 253), regex = "KEY_PATTERN")]
     pub name: String,
+    /// Handlebars template, inputs are available by their names
     pub template: String,
+    /// Name of the target from the targets list
+    #[schemars(length(min = 1, max = 253))]
     pub target: String,
 }
+
+/// A single problem found in a ConfigSet spec
+#[derive(Error, Debug, PartialEq)]
+pub enum ValidationError {
+    #[error("target name {0} is used more than once")]
+    DuplicateTarget(String),
+    #[error("template {template} refers to an unknown target {target}")]
+    UnknownTarget { template: String, target: String },
+    #[error("template {template} can't be parsed: {reason}")]
+    InvalidTemplate { template: String, reason: String },
+    #[error("key {key} is written to target {target} by more than one template")]
+    DuplicateKey { target: String, key: String },
+    #[error("{0} is not a valid key for Secrets and ConfigMaps")]
+    InvalidKey(String),
+    #[error("refresh interval {0} is not a valid duration")]
+    InvalidRefreshInterval(String),
+    #[error("namespace selector of target {target} is invalid: {reason}")]
+    InvalidSelector { target: String, reason: String },
+}
+
+/// All the problems found in a ConfigSet spec
+#[derive(Debug)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<String> = self.0.iter().map(|err| err.to_string()).collect();
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl ConfigSetSpec {
+    /// Check the spec for problems that would make the reconciliation fail.
+    /// It's used by both the reconciler and the admission webhook,
+    /// so they always agree on what is a valid ConfigSet
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors: Vec<ValidationError> = vec![];
+
+        let mut target_names: HashSet<&str> = HashSet::new();
+        for target in self.targets.iter() {
+            if !target_names.insert(target.name.as_str()) {
+                errors.push(ValidationError::DuplicateTarget(target.name.clone()));
+            }
+        }
+
+        for input in self.inputs.iter() {
+            if !is_valid_key(input.from.key.as_str()) {
+                errors.push(ValidationError::InvalidKey(input.from.key.clone()));
+            }
+        }
+
+        let mut keys: HashSet<(&str, &str)> = HashSet::new();
+        for template in self.templates.iter() {
+            if !is_valid_key(template.name.as_str()) {
+                errors.push(ValidationError::InvalidKey(template.name.clone()));
+            }
+            if !target_names.contains(template.target.as_str()) {
+                errors.push(ValidationError::UnknownTarget {
+                    template: template.name.clone(),
+                    target: template.target.clone(),
+                });
+            }
+            if let Err(err) = Template::compile(template.template.as_str()) {
+                errors.push(ValidationError::InvalidTemplate {
+                    template: template.name.clone(),
+                    reason: err.to_string(),
+                });
+            }
+            if !keys.insert((template.target.as_str(), template.name.as_str())) {
+                errors.push(ValidationError::DuplicateKey {
+                    target: template.target.clone(),
+                    key: template.name.clone(),
+                });
+            }
+        }
+
+        if let Some(interval) = &self.refresh_interval {
+            if !matches!(parse_duration(interval), Ok(duration) if !duration.is_zero()) {
+                errors.push(ValidationError::InvalidRefreshInterval(interval.clone()));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    /// Interval after which the ConfigSet is reconciled again, if it's set and valid
+    pub fn refresh_duration(&self) -> Option<Duration> {
+        self.refresh_interval
+            .as_ref()
+            .and_then(|interval| parse_duration(interval).ok())
+            .filter(|duration| !duration.is_zero())
+    }
+}
@@ -1 +1,2 @@
+pub mod clusterconfigsets_api;
 pub mod configsets_api;
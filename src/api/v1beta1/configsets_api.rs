@@ -0,0 +1,322 @@
+use crate::api::schema::{
+    list_map_schema, refresh_interval_schema, KEY_PATTERN, MAX_INPUTS, MAX_TARGETS, MAX_TEMPLATES,
+    OBJECT_NAME_PATTERN,
+};
+use crate::api::v1alpha1::configsets_api as v1alpha1;
+pub use crate::api::v1alpha1::configsets_api::{ConfigSetStatus, Kinds};
+use kube::CustomResource;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+
+/// ConfigSet is the main CRD of the shoebill-operator.
+/// During the reconciliation, the controller will get the data
+/// from Secrets and ConfigMaps defined in inputs, use them for
+/// building new variables, that are defined in templates, and
+/// put them to target Secrets or ConfigMaps
+///
+/// Comparing to v1alpha1, inputs and targets refer to objects like
+/// Pods do (`secretKeyRef`, `configMapKeyRef`, `secret`, `configMap`),
+/// and templates are writing to keys instead of having names.
+/// Every kind has its own field, so options that only make sense
+/// for one kind can be added without breaking the others
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[cfg_attr(test, derive(Default))]
+#[kube(
+    kind = "ConfigSet",
+    group = "shoebill.badhouseplants.net",
+    version = "v1beta1",
+    namespaced
+)]
+#[kube(
+    status = "ConfigSetStatus",
+    shortname = "confset",
+    category = "shoebill"
+)]
+#[kube(
+    printcolumn = r#"{"name":"Ready","type":"boolean","jsonPath":".status.ready"}"#,
+    printcolumn = r#"{"name":"Reason","type":"string","jsonPath":".status.reason"}"#,
+    printcolumn = r#"{"name":"Targets","type":"integer","jsonPath":".status.targets"}"#,
+    printcolumn = r#"{"name":"Last Sync","type":"date","jsonPath":".status.lastSyncTime"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigSetSpec {
+    pub inputs: Vec<Input>,
+    pub targets: Vec<Target>,
+    pub templates: Vec<Template>,
+    /// Reconcile the ConfigSet again after the interval, e.g. 10m
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_interval: Option<String>,
+}
+
+// The schema is written by hand for the same reason as in v1alpha1
+impl JsonSchema for ConfigSetSpec {
+    fn schema_name() -> String {
+        "ConfigSetSpec".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            ..Default::default()
+        };
+        let object = schema.object();
+        object.properties.insert(
+            "inputs".to_string(),
+            list_map_schema::<Input>(
+                gen,
+                "Values from Secrets and ConfigMaps that can be used in templates",
+                &["name"],
+                MAX_INPUTS,
+            ),
+        );
+        object.properties.insert(
+            "targets".to_string(),
+            list_map_schema::<Target>(
+                gen,
+                "Secrets and ConfigMaps that should be populated with rendered templates",
+                &["name"],
+                MAX_TARGETS,
+            ),
+        );
+        object.properties.insert(
+            "templates".to_string(),
+            list_map_schema::<Template>(
+                gen,
+                "Handlebars templates that are rendered with inputs and written to targets",
+                &["target", "key"],
+                MAX_TEMPLATES,
+            ),
+        );
+        object
+            .properties
+            .insert("refreshInterval".to_string(), refresh_interval_schema());
+        object.required = BTreeSet::from([
+            "inputs".to_string(),
+            "targets".to_string(),
+            "templates".to_string(),
+        ]);
+        schema.extensions.insert(
+            "x-kubernetes-validations".to_string(),
+            json!([
+                {
+                    "rule": "self.templates.all(t, self.targets.exists(x, x.name == t.target))",
+                    "message": "every template must refer to a target defined in targets",
+                },
+                {
+                    "rule": "self.inputs.all(i, has(i.secretKeyRef) != has(i.configMapKeyRef))",
+                    "message": "every input must set exactly one of secretKeyRef and configMapKeyRef",
+                },
+                {
+                    "rule": "self.targets.all(t, has(t.secret) != has(t.configMap))",
+                    "message": "every target must set exactly one of secret and configMap",
+                },
+            ]),
+        );
+        Schema::Object(schema)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Input {
+    /// Name of the variable that can be used in templates
+    #[schemars(length(min = 1, max = 253))]
+    pub name: String,
+    /// Take the value from a key of a Secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_key_ref: Option<KeySelector>,
+    /// Take the value from a key of a ConfigMap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_map_key_ref: Option<KeySelector>,
+}
+
+/// A key of a Secret or a ConfigMap in the namespace of the ConfigSet
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KeySelector {
+    /// Name of the object
+    #[schemars(length(min = 1, max = 253), regex = "OBJECT_NAME_PATTERN")]
+    pub name: String,
+    /// Key of the value in the object
+    #[schemars(length(min = 1, max = 253), regex = "KEY_PATTERN")]
+    pub key: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Target {
+    /// Name of the target that is used by templates
+    #[schemars(length(min = 1, max = 253))]
+    pub name: String,
+    /// Write rendered templates to a Secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<LocalObjectReference>,
+    /// Write rendered templates to a ConfigMap
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_map: Option<LocalObjectReference>,
+}
+
+/// A Secret or a ConfigMap in the namespace of the ConfigSet,
+/// it's created if it doesn't exist
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalObjectReference {
+    /// Name of the object
+    #[schemars(length(min = 1, max = 253), regex = "OBJECT_NAME_PATTERN")]
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    /// Key that the rendered template is written to
+    #[schemars(length(min = 1, max = 253), regex = "KEY_PATTERN")]
+    pub key: String,
+    /// Name of the target from the targets list
+    #[schemars(length(min = 1, max = 253))]
+    pub target: String,
+    /// Handlebars template, inputs are available by their names
+    pub template: String,
+}
+
+// Both versions have exactly the same set of fields, so objects can be converted
+// back and forth without losing anything. Inputs and targets with none or both
+// of the references are rejected by the schema, the conversion still has to
+// produce an object for them, so the Secret is preferred, and one without a name
+// is used when none is set
+
+impl From<v1alpha1::ConfigSetSpec> for ConfigSetSpec {
+    fn from(spec: v1alpha1::ConfigSetSpec) -> Self {
+        ConfigSetSpec {
+            inputs: spec
+                .inputs
+                .into_iter()
+                .map(|input| {
+                    let selector = KeySelector {
+                        name: input.from.name,
+                        key: input.from.key,
+                    };
+                    let (secret_key_ref, config_map_key_ref) = match input.from.kind {
+                        Kinds::Secret => (Some(selector), None),
+                        Kinds::ConfigMap => (None, Some(selector)),
+                    };
+                    Input {
+                        name: input.name,
+                        secret_key_ref,
+                        config_map_key_ref,
+                    }
+                })
+                .collect(),
+            targets: spec
+                .targets
+                .into_iter()
+                .map(|target| {
+                    let reference = LocalObjectReference {
+                        name: target.target.name,
+                    };
+                    let (secret, config_map) = match target.target.kind {
+                        Kinds::Secret => (Some(reference), None),
+                        Kinds::ConfigMap => (None, Some(reference)),
+                    };
+                    Target {
+                        name: target.name,
+                        secret,
+                        config_map,
+                    }
+                })
+                .collect(),
+            templates: spec
+                .templates
+                .into_iter()
+                .map(|template| Template {
+                    key: template.name,
+                    target: template.target,
+                    template: template.template,
+                })
+                .collect(),
+            refresh_interval: spec.refresh_interval,
+        }
+    }
+}
+
+impl From<ConfigSetSpec> for v1alpha1::ConfigSetSpec {
+    fn from(spec: ConfigSetSpec) -> Self {
+        v1alpha1::ConfigSetSpec {
+            targets: spec
+                .targets
+                .into_iter()
+                .map(|target| {
+                    let (kind, name) = match (target.secret, target.config_map) {
+                        (Some(secret), _) => (Kinds::Secret, secret.name),
+                        (None, Some(config_map)) => (Kinds::ConfigMap, config_map.name),
+                        (None, None) => (Kinds::Secret, String::new()),
+                    };
+                    v1alpha1::TargetWithName {
+                        name: target.name,
+                        target: v1alpha1::Target { kind, name },
+                    }
+                })
+                .collect(),
+            inputs: spec
+                .inputs
+                .into_iter()
+                .map(|input| {
+                    let (kind, selector) = match (input.secret_key_ref, input.config_map_key_ref) {
+                        (Some(selector), _) => (Kinds::Secret, selector),
+                        (None, Some(selector)) => (Kinds::ConfigMap, selector),
+                        (None, None) => (
+                            Kinds::Secret,
+                            KeySelector {
+                                name: String::new(),
+                                key: String::new(),
+                            },
+                        ),
+                    };
+                    v1alpha1::InputWithName {
+                        name: input.name,
+                        from: v1alpha1::Input {
+                            kind,
+                            name: selector.name,
+                            key: selector.key,
+                        },
+                    }
+                })
+                .collect(),
+            templates: spec
+                .templates
+                .into_iter()
+                .map(|template| v1alpha1::Templates {
+                    name: template.key,
+                    template: template.template,
+                    target: template.target,
+                })
+                .collect(),
+            refresh_interval: spec.refresh_interval,
+        }
+    }
+}
+
+impl From<v1alpha1::ConfigSet> for ConfigSet {
+    fn from(confset: v1alpha1::ConfigSet) -> Self {
+        ConfigSet {
+            metadata: confset.metadata,
+            spec: confset.spec.into(),
+            status: confset.status,
+        }
+    }
+}
+
+impl From<ConfigSet> for v1alpha1::ConfigSet {
+    fn from(confset: ConfigSet) -> Self {
+        v1alpha1::ConfigSet {
+            metadata: confset.metadata,
+            spec: confset.spec.into(),
+            status: confset.status,
+        }
+    }
+}
@@ -0,0 +1 @@
+pub mod configsets_api;
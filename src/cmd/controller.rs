@@ -2,8 +2,71 @@ use clap::Args;
 
 #[derive(Args)]
 pub(crate) struct ControllerArgs {
-    /// Use this flag if you want to let shoebill
-    /// update secrets that already exist in the cluster
-    #[arg(long, default_value_t = false, env = "SHOEBILL_ALLOW_EXISTING")]
-    pub(crate) allow_existing: bool,
+    /// Path to the TLS certificate for the admission webhook,
+    /// webhooks are only served when both cert and key are set
+    #[arg(long, env = "SHOEBILL_WEBHOOK_CERT", requires = "webhook_key")]
+    pub(crate) webhook_cert: Option<String>,
+    /// Path to the TLS private key for the admission webhook
+    #[arg(long, env = "SHOEBILL_WEBHOOK_KEY", requires = "webhook_cert")]
+    pub(crate) webhook_key: Option<String>,
+    /// Port to serve webhooks on
+    #[arg(long, default_value_t = 8443, env = "SHOEBILL_WEBHOOK_PORT")]
+    pub(crate) webhook_port: u16,
+    /// Elect a leader using a Lease, so several replicas can be running,
+    /// while only one of them is reconciling
+    #[arg(long, default_value_t = false, env = "SHOEBILL_LEADER_ELECTION")]
+    pub(crate) leader_election: bool,
+    /// Name of the Lease that is used for leader election
+    #[arg(
+        long,
+        default_value = "shoebill-controller",
+        env = "SHOEBILL_LEASE_NAME"
+    )]
+    pub(crate) lease_name: String,
+    /// Namespace of the Lease, the namespace of the pod is used by default
+    #[arg(long, env = "SHOEBILL_LEASE_NAMESPACE")]
+    pub(crate) lease_namespace: Option<String>,
+    /// Seconds a leader holds the Lease without renewing it
+    #[arg(long, default_value_t = 15, env = "SHOEBILL_LEASE_DURATION")]
+    pub(crate) lease_duration: u64,
+    /// Seconds the leader keeps reconciling without renewing the Lease,
+    /// it must be shorter than the lease duration
+    #[arg(long, default_value_t = 10, env = "SHOEBILL_LEASE_RENEW_DEADLINE")]
+    pub(crate) lease_renew_deadline: u64,
+    /// Identity of the replica in the Lease, the hostname is used by default
+    #[arg(long, env = "SHOEBILL_LEASE_IDENTITY")]
+    pub(crate) lease_identity: Option<String>,
+    /// Only watch ConfigSets and manage targets in these namespaces,
+    /// ClusterConfigSets are not reconciled in this mode
+    #[arg(long, value_delimiter = ',', env = "SHOEBILL_WATCH_NAMESPACES")]
+    pub(crate) watch_namespaces: Vec<String>,
+    /// Only reconcile ConfigSets and ClusterConfigSets matching the label selector
+    #[arg(long, env = "SHOEBILL_CONFIGSET_SELECTOR")]
+    pub(crate) configset_selector: Option<String>,
+    /// Seconds before the first retry of a failed reconciliation,
+    /// the delay is doubled on every next failure
+    #[arg(long, default_value_t = 5, env = "SHOEBILL_MIN_BACKOFF")]
+    pub(crate) min_backoff: u64,
+    /// Max seconds between retries of a failed reconciliation
+    #[arg(long, default_value_t = 300, env = "SHOEBILL_MAX_BACKOFF")]
+    pub(crate) max_backoff: u64,
+    /// Only cache Secrets and ConfigMaps matching the label selector,
+    /// other ones are read from the API every time they are used
+    #[arg(long, env = "SHOEBILL_CACHE_SELECTOR")]
+    pub(crate) cache_selector: Option<String>,
+    /// How many objects can be reconciled at the same time by every controller, 0 is unlimited
+    #[arg(long, default_value_t = 0, env = "SHOEBILL_MAX_CONCURRENT_RECONCILES")]
+    pub(crate) max_concurrent_reconciles: u16,
+    /// Seconds after which successfully reconciled objects are reconciled again,
+    /// objects with refreshInterval in the spec use it instead
+    #[arg(long, env = "SHOEBILL_RESYNC_INTERVAL")]
+    pub(crate) resync_interval: Option<u64>,
+    /// Don't change targets, log what would be changed and record it in
+    /// the status of ConfigSets. Writes are validated with server-side dry-run
+    #[arg(long, env = "SHOEBILL_DRY_RUN")]
+    pub(crate) dry_run: bool,
+    /// OTLP gRPC endpoint that spans of reconciliations are exported to,
+    /// e.g. http://localhost:4317. Spans are not recorded if it's not set
+    #[arg(long, env = "SHOEBILL_OTLP_ENDPOINT")]
+    pub(crate) otlp_endpoint: Option<String>,
 }
@@ -0,0 +1,15 @@
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct DiffArgs {
+    /// ConfigSet manifest, both v1alpha1 and v1beta1 are accepted
+    #[arg(long, short)]
+    pub(crate) file: String,
+    /// Namespace of inputs and targets, by default the one of the ConfigSet
+    /// or of the current kube context
+    #[arg(long, short)]
+    pub(crate) namespace: Option<String>,
+    /// Print values of Secrets instead of their hashes
+    #[arg(long, default_value_t = false)]
+    pub(crate) show_secrets: bool,
+}
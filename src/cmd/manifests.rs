@@ -1,4 +1,6 @@
-use clap::{Args, Command, Parser, Subcommand};
+use ::controller::helpers::manifests::ManifestsFormat;
+use ::controller::logging::LogFormat;
+use clap::Args;
 
 #[derive(Args)]
 pub(crate) struct ManifestsArgs {
@@ -8,4 +10,56 @@ pub(crate) struct ManifestsArgs {
     pub(crate) tag: String,
     #[arg(long, short, default_value = "shoebill")]
     pub(crate) image: String,
+    /// Add the validating admission webhook to the manifests,
+    /// it requires cert-manager to be installed in the cluster
+    #[arg(long, default_value_t = false)]
+    pub(crate) webhook: bool,
+    /// Only serve ConfigSets as v1alpha1, without the conversion webhook.
+    /// By default v1beta1 is served too, and it requires cert-manager to be
+    /// installed in the cluster. The Helm chart always serves both versions
+    #[arg(long = "no-conversion-webhook", action = clap::ArgAction::SetFalse)]
+    pub(crate) conversion_webhook: bool,
+    /// Number of controller replicas, only the elected leader is reconciling
+    #[arg(long, default_value_t = 1)]
+    pub(crate) replicas: i32,
+    /// Only watch these namespaces, namespaced Roles are generated
+    /// instead of the ClusterRole
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) watch_namespaces: Vec<String>,
+    /// Only reconcile ConfigSets matching the label selector
+    #[arg(long)]
+    pub(crate) configset_selector: Option<String>,
+    /// Value of RUST_LOG of the controller
+    #[arg(long, default_value = "info")]
+    pub(crate) log_level: String,
+    /// Format of log lines of the controller, the global --log-format
+    /// only sets the format of this command
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub(crate) controller_log_format: LogFormat,
+    /// CPU request of the controller container
+    #[arg(long, default_value = "50m")]
+    pub(crate) requests_cpu: String,
+    /// Memory request of the controller container
+    #[arg(long, default_value = "64Mi")]
+    pub(crate) requests_memory: String,
+    /// CPU limit of the controller container, it's not set by default
+    #[arg(long)]
+    pub(crate) limits_cpu: Option<String>,
+    /// Memory limit of the controller container
+    #[arg(long, default_value = "256Mi")]
+    pub(crate) limits_memory: String,
+    /// Add a ServiceMonitor for the Prometheus operator
+    #[arg(long, default_value_t = false)]
+    pub(crate) service_monitor: bool,
+    /// Print YAML documents, or write a Helm chart or a Kustomize base.
+    /// Settings above are defaults of values of the chart
+    #[arg(long, value_enum, default_value_t = ManifestsFormat::Yaml)]
+    pub(crate) format: ManifestsFormat,
+    /// Directory the chart or the base is written to
+    #[arg(long, short, default_value = "shoebill")]
+    pub(crate) output: String,
+    /// Flags that are passed to the controller as they are, e.g.
+    /// `shoebill manifests -- --resync-interval=600 --dry-run`
+    #[arg(last = true)]
+    pub(crate) controller_args: Vec<String>,
 }
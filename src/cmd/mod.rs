@@ -1,15 +1,25 @@
-use clap::{command, Parser, Subcommand};
+use ::controller::logging::LogFormat;
+use clap::{Parser, Subcommand};
 
 use self::controller::ControllerArgs;
+use self::diff::DiffArgs;
 use self::manifests::ManifestsArgs;
+use self::render::RenderArgs;
+use self::validate::ValidateArgs;
 
 pub(crate) mod controller;
+pub(crate) mod diff;
 pub(crate) mod manifests;
+pub(crate) mod render;
+pub(crate) mod validate;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 pub(crate) struct Cli {
+    /// Format of log lines, values of inputs and templates are redacted in both
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text, env = "SHOEBILL_LOG_FORMAT")]
+    pub(crate) log_format: LogFormat,
     #[command(subcommand)]
     pub(crate) command: Commands,
 }
@@ -20,4 +30,10 @@ pub(crate) enum Commands {
     Controller(ControllerArgs),
     // Generate manifests for quick install
     Manifests(ManifestsArgs),
+    // Render a ConfigSet with inputs from local files, without a cluster
+    Render(RenderArgs),
+    // Check ConfigSet manifests for problems, without a cluster
+    Validate(ValidateArgs),
+    // Compare a rendered ConfigSet with its targets in the current kube context
+    Diff(DiffArgs),
 }
@@ -0,0 +1,15 @@
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct RenderArgs {
+    /// ConfigSet manifest, both v1alpha1 and v1beta1 are accepted
+    #[arg(long, short)]
+    pub(crate) file: String,
+    /// Secrets and ConfigMaps that are used as inputs and targets,
+    /// a file may contain many YAML documents
+    #[arg(long, short)]
+    pub(crate) input: Vec<String>,
+    /// Replace values of Secrets with a placeholder
+    #[arg(long, default_value_t = false)]
+    pub(crate) mask_secrets: bool,
+}
@@ -0,0 +1,12 @@
+use ::controller::helpers::validate::ReportFormat;
+use clap::Args;
+
+#[derive(Args)]
+pub(crate) struct ValidateArgs {
+    /// Files with ConfigSets, other objects in them are ignored
+    #[arg(required = true)]
+    pub(crate) files: Vec<String>,
+    /// Format of the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Human)]
+    pub(crate) format: ReportFormat,
+}
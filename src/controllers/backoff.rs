@@ -0,0 +1,45 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Per-object exponential backoff for failed reconciliations.
+/// The delay is doubled on every failure up to the max,
+/// and a random jitter is added, so objects that failed at the same
+/// time are not retried all at once
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    // Number of failures in a row by object
+    failures: Mutex<HashMap<String, u32>>,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff {
+            min,
+            max: max.max(min),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a failure of the object and get the delay before the next attempt
+    pub(crate) fn next_delay(&self, object: &str) -> Duration {
+        let mut failures = self.failures.lock().unwrap();
+        let attempt = failures.entry(object.to_string()).or_default();
+        let delay = self
+            .min
+            .checked_mul(2_u32.saturating_pow(*attempt))
+            .unwrap_or(self.max)
+            .min(self.max);
+        *attempt = attempt.saturating_add(1);
+        // Equal jitter, the delay is between a half and the full value
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+    /// Forget failures of the object after a successful reconciliation
+    pub(crate) fn reset(&self, object: &str) {
+        self.failures.lock().unwrap().remove(object);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::controllers::configsets_controller::{watch_initial_sync, watcher_config};
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::{Error, Result};
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use k8s_openapi::NamespaceResourceScope;
+use kube::core::PartialObjectMeta;
+use kube::runtime::reflector::{ObjectRef, Store};
+use kube::runtime::{metadata_watcher, reflector, watcher, WatchStreamExt};
+use kube::{Api, Client, Resource, ResourceExt};
+use log::*;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// Full objects that were read by reconcilers, by namespaces and names
+type Objects<K> = Arc<Mutex<HashMap<(String, String), Arc<K>>>>;
+
+// Stores of a single namespace, or of the whole cluster when it's not set
+#[derive(Clone)]
+pub(crate) struct Scope {
+    namespace: Option<String>,
+    secrets: Store<PartialObjectMeta<Secret>>,
+    configmaps: Store<PartialObjectMeta<ConfigMap>>,
+}
+
+/// Secrets and ConfigMaps that are shared by all the controllers.
+/// Only metadata of objects is watched, full objects are read from the API
+/// once they are referenced by inputs or targets, and they are kept until
+/// their resource version is changed. So unchanged objects are read from
+/// memory, and unreferenced ones are never held. Reads from the API are
+/// counted in metrics
+#[derive(Clone)]
+pub struct Cache {
+    client: Client,
+    scopes: Vec<Scope>,
+    // Without a selector all the objects are in stores, so objects
+    // that are missing in them don't exist
+    complete: bool,
+    secrets: Objects<Secret>,
+    configmaps: Objects<ConfigMap>,
+    metrics: Arc<Metrics>,
+}
+
+/// Kinds of objects that are cached
+pub(crate) trait Cached:
+    Resource<DynamicType = (), Scope = NamespaceResourceScope>
+    + Clone
+    + DeserializeOwned
+    + Debug
+    + Send
+    + Sync
+    + 'static
+{
+    fn store(scope: &Scope) -> &Store<PartialObjectMeta<Self>>;
+    fn objects(cache: &Cache) -> &Objects<Self>;
+}
+
+impl Cached for Secret {
+    fn store(scope: &Scope) -> &Store<PartialObjectMeta<Self>> {
+        &scope.secrets
+    }
+
+    fn objects(cache: &Cache) -> &Objects<Self> {
+        &cache.secrets
+    }
+}
+
+impl Cached for ConfigMap {
+    fn store(scope: &Scope) -> &Store<PartialObjectMeta<Self>> {
+        &scope.configmaps
+    }
+
+    fn objects(cache: &Cache) -> &Objects<Self> {
+        &cache.configmaps
+    }
+}
+
+impl Cache {
+    /// Start watchers for the namespaces, or for the whole cluster if there are none
+    pub fn start(
+        client: Client,
+        namespaces: &[String],
+        selector: Option<String>,
+        metrics: Arc<Metrics>,
+        health: Arc<Health>,
+    ) -> Self {
+        let namespaces: Vec<Option<String>> = if namespaces.is_empty() {
+            vec![None]
+        } else {
+            namespaces.iter().cloned().map(Some).collect()
+        };
+        let secrets: Objects<Secret> = Arc::default();
+        let configmaps: Objects<ConfigMap> = Arc::default();
+        let scopes = namespaces
+            .into_iter()
+            .map(|namespace| Scope {
+                secrets: reflect(&client, &namespace, &selector, &health, secrets.clone()),
+                configmaps: reflect(&client, &namespace, &selector, &health, configmaps.clone()),
+                namespace,
+            })
+            .collect();
+        Cache {
+            client,
+            scopes,
+            complete: selector.is_none(),
+            secrets,
+            configmaps,
+            metrics,
+        }
+    }
+
+    fn scope(&self, namespace: &str) -> Option<&Scope> {
+        self.scopes.iter().find(|scope| match &scope.namespace {
+            Some(scoped) => scoped == namespace,
+            None => true,
+        })
+    }
+
+    /// Wait until all the watchers got the initial list of objects,
+    /// otherwise every lookup would be a read from the API
+    pub(crate) async fn wait_until_synced(&self) {
+        for scope in self.scopes.iter() {
+            let _ = scope.secrets.wait_until_ready().await;
+            let _ = scope.configmaps.wait_until_ready().await;
+        }
+    }
+
+    pub(crate) async fn get_secret(&self, namespace: &str, name: &str) -> Result<Option<Secret>> {
+        self.get(namespace, name).await
+    }
+
+    pub(crate) async fn get_configmap(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<ConfigMap>> {
+        self.get(namespace, name).await
+    }
+
+    /// Drop the object, so it's read from the API next time,
+    /// e.g. when a write has failed because of its outdated version
+    pub(crate) fn forget<K: Cached>(&self, namespace: &str, name: &str) {
+        K::objects(self)
+            .lock()
+            .unwrap()
+            .remove(&(namespace.to_string(), name.to_string()));
+    }
+
+    async fn get<K: Cached>(&self, namespace: &str, name: &str) -> Result<Option<K>> {
+        let key = (namespace.to_string(), name.to_string());
+        let watched = match self.scope(namespace) {
+            Some(scope) => K::store(scope).get(&ObjectRef::new(name).within(namespace)),
+            None => None,
+        };
+        match &watched {
+            Some(meta) => {
+                let objects = K::objects(self).lock().unwrap();
+                if let Some(object) = objects.get(&key) {
+                    if object.resource_version() == meta.resource_version() {
+                        return Ok(Some(object.as_ref().clone()));
+                    }
+                }
+            }
+            None if self.complete && self.scope(namespace).is_some() => return Ok(None),
+            None => {}
+        }
+        debug!(
+            "{} {}/{} is not cached, reading it from the API",
+            K::kind(&()),
+            namespace,
+            name
+        );
+        self.metrics.cache_miss(&K::plural(&()));
+        let object = match Api::<K>::namespaced(self.client.clone(), namespace)
+            .get_opt(name)
+            .await
+        {
+            Ok(object) => object,
+            Err(err) => {
+                error!("{err}");
+                return Err(Error::KubeError(err));
+            }
+        };
+        // Only watched objects are kept, because others can't be invalidated
+        if let (Some(object), Some(_)) = (&object, &watched) {
+            K::objects(self)
+                .lock()
+                .unwrap()
+                .insert(key, Arc::new(object.clone()));
+        }
+        Ok(object)
+    }
+}
+
+// Full objects are dropped when they are changed or deleted, so they are not held
+// after the change, even if they are never read again
+fn invalidate<K: Cached>(objects: &Objects<K>, event: &watcher::Event<PartialObjectMeta<K>>) {
+    let key = |meta: &PartialObjectMeta<K>| (meta.namespace().unwrap_or_default(), meta.name_any());
+    let mut objects = objects.lock().unwrap();
+    match event {
+        watcher::Event::Applied(meta) => {
+            if let Some(object) = objects.get(&key(meta)) {
+                if object.resource_version() != meta.resource_version() {
+                    objects.remove(&key(meta));
+                }
+            }
+        }
+        watcher::Event::Deleted(meta) => {
+            objects.remove(&key(meta));
+        }
+        watcher::Event::Restarted(metas) => {
+            let versions: HashMap<(String, String), Option<String>> = metas
+                .iter()
+                .map(|meta| (key(meta), meta.resource_version()))
+                .collect();
+            objects.retain(|key, object| versions.get(key) == Some(&object.resource_version()));
+        }
+    }
+}
+
+// Watch metadata of objects in the background, the store is shared by all the reconcilers
+fn reflect<K: Cached>(
+    client: &Client,
+    namespace: &Option<String>,
+    selector: &Option<String>,
+    health: &Arc<Health>,
+    objects: Objects<K>,
+) -> Store<PartialObjectMeta<K>> {
+    let api = match namespace {
+        Some(namespace) => Api::<K>::namespaced(client.clone(), namespace),
+        None => Api::<K>::all(client.clone()),
+    };
+    let kind = K::plural(&()).to_string();
+    let health_key = match namespace {
+        Some(namespace) => format!("{}/{}", kind, namespace),
+        None => kind,
+    };
+    health.register(&health_key);
+    // The client is already configured, it's only the API that may be unavailable
+    health.set_client_connected(&health_key, true);
+    health.set_crds_installed(&health_key, true);
+
+    let (reader, writer) = reflector::store();
+    watch_initial_sync(reader.clone(), health.clone(), health_key.clone());
+    let health = health.clone();
+    let stream = metadata_watcher(api, watcher_config(selector))
+        .default_backoff()
+        .reflect(writer)
+        .for_each(move |event| {
+            match event {
+                Ok(event) => {
+                    invalidate(&objects, &event);
+                    health.watch_succeeded(&health_key);
+                }
+                Err(err) => {
+                    error!("{} watcher has failed: {}", health_key, err);
+                    health.watch_failed(&health_key);
+                }
+            }
+            futures::future::ready(())
+        });
+    tokio::spawn(stream);
+    reader
+}
+
+#[cfg(test)]
+impl Cache {
+    /// A cache of the whole cluster that is already synced with the objects,
+    /// all of them are already read by reconcilers
+    pub(crate) fn with_objects(
+        client: Client,
+        secrets: Vec<Secret>,
+        configmaps: Vec<ConfigMap>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        fn synced<K: Cached>(objects: Vec<K>) -> (Store<PartialObjectMeta<K>>, Objects<K>) {
+            let (reader, mut writer) = reflector::store();
+            let metas = objects
+                .iter()
+                .map(|object| PartialObjectMeta {
+                    types: None,
+                    metadata: object.meta().clone(),
+                    _phantom: Default::default(),
+                })
+                .collect();
+            writer.apply_watcher_event(&watcher::Event::Restarted(metas));
+            let objects = objects
+                .into_iter()
+                .map(|object| {
+                    let key = (object.namespace().unwrap_or_default(), object.name_any());
+                    (key, Arc::new(object))
+                })
+                .collect();
+            (reader, Arc::new(Mutex::new(objects)))
+        }
+        let (secrets_store, secrets) = synced(secrets);
+        let (configmaps_store, configmaps) = synced(configmaps);
+        Cache {
+            client,
+            scopes: vec![Scope {
+                namespace: None,
+                secrets: secrets_store,
+                configmaps: configmaps_store,
+            }],
+            complete: true,
+            secrets,
+            configmaps,
+            metrics,
+        }
+    }
+}
@@ -0,0 +1,685 @@
+use crate::api::v1alpha1::clusterconfigsets_api::{
+    ClusterConfigSet, ClusterConfigSetStatus, ClusterTargetWithName,
+};
+use crate::api::v1alpha1::configsets_api::{TargetWithName, Templates};
+use crate::controllers::backoff::Backoff;
+use crate::controllers::cache::Cache;
+use crate::controllers::configsets_controller::{
+    build_owner_refenerce, build_templates, cleanup_templates, connect, gather_existing_targets,
+    get_input_value, is_paused, paused_condition, prepare_targets, retry_action,
+    watch_initial_sync, watcher_config, write_targets, Context, PAUSED_CONDITION,
+    RECONCILE_AT_ANNOTATION, SHU_FIELD_MANAGER, SHU_FINALIZER,
+};
+use crate::controllers::events::{
+    Events, ACTION_CLEANUP, ACTION_RECONCILE, CLEANUP_FAILED, INPUT_MISSING, TEMPLATE_ERROR,
+};
+use crate::controllers::ReconcileSettings;
+use crate::health::Health;
+use crate::logging::{self, LogContext};
+use crate::metrics::Metrics;
+use crate::{Error, Result};
+use chrono::Utc;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Namespace;
+use kube::api::{Patch, PatchParams};
+use kube::core::PartialObjectMeta;
+use kube::runtime::controller::Action;
+use kube::runtime::finalizer::Event as Finalizer;
+use kube::runtime::reflector::ObjectRef;
+use kube::runtime::watcher::Config;
+use kube::runtime::{finalizer, metadata_watcher, reflector, watcher, Controller, WatchStreamExt};
+use kube::{Api, Resource, ResourceExt};
+use log::*;
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tracing::{info_span, Instrument};
+
+/// Kind of objects handled by the controller, used in metrics and health checks
+static CONTROLLER_KIND: &str = "clusterconfigset";
+
+async fn reconcile(ccsupstream: Arc<ClusterConfigSet>, ctx: Arc<Context>) -> Result<Action> {
+    let context = LogContext::new(ccsupstream.name_any(), None);
+    let span = info_span!(
+        "reconcile",
+        clusterconfigset = %ccsupstream.name_any(),
+        reconcile_id = context.reconcile_id(),
+    );
+    // Errors are handled outside of the scope, so they are redacted while values are known
+    let reconcile = async {
+        reconcile_clusterconfigset(ccsupstream, ctx)
+            .await
+            .map_err(Error::redacted)
+    };
+    logging::scope(context, reconcile).instrument(span).await
+}
+
+async fn reconcile_clusterconfigset(
+    ccsupstream: Arc<ClusterConfigSet>,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    let cconfset: Api<ClusterConfigSet> = Api::all(ctx.client.clone());
+    let _timer = ctx.metrics.reconcile_timer(CONTROLLER_KIND);
+    finalizer(
+        &cconfset,
+        SHU_FINALIZER,
+        ccsupstream.clone(),
+        |event| async {
+            info!("reconciling {}", ccsupstream.name_any());
+            match event {
+                // Same as for ConfigSets, only the status is updated while paused
+                Finalizer::Apply(_doc) if is_paused(ccsupstream.as_ref()) => {
+                    info!("{} is paused", ccsupstream.name_any());
+                    ccsupstream
+                        .update_status(ctx.clone(), &Ok(Synced::default()))
+                        .await?;
+                    Ok(Action::await_change())
+                }
+                Finalizer::Apply(_doc) => {
+                    let res = ccsupstream.reconcile(ctx.clone()).await;
+                    match &res {
+                        Ok(synced) => {
+                            info!("reconciled successfully");
+                            ctx.backoff
+                                .reset(&ObjectRef::from_obj(ccsupstream.as_ref()).to_string());
+                            ctx.metrics.reconcile_success(CONTROLLER_KIND);
+                            ctx.metrics.set_targets(
+                                CONTROLLER_KIND,
+                                ObjectRef::from_obj(ccsupstream.as_ref()).to_string(),
+                                synced.targets,
+                            );
+                        }
+                        Err(err) => {
+                            error!("reconciliation has failed with error: {}", err);
+                            ctx.metrics
+                                .reconcile_failure(CONTROLLER_KIND, &err.metric_label());
+                        }
+                    };
+                    ccsupstream.update_status(ctx.clone(), &res).await?;
+                    res.map(|_| ctx.success_action(ccsupstream.spec.refresh_duration()))
+                }
+                Finalizer::Cleanup(_doc) => match ccsupstream.cleanup(ctx.clone()).await {
+                    Ok(res) => {
+                        info!("cleaned up successfully");
+                        ctx.backoff
+                            .reset(&ObjectRef::from_obj(ccsupstream.as_ref()).to_string());
+                        ctx.metrics.remove_object(
+                            CONTROLLER_KIND,
+                            &ObjectRef::from_obj(ccsupstream.as_ref()).to_string(),
+                        );
+                        Ok(res)
+                    }
+                    Err(err) => {
+                        error!("cleanup has failed with error: {}", err);
+                        ctx.metrics
+                            .reconcile_failure(CONTROLLER_KIND, &err.metric_label());
+                        ctx.events
+                            .warning(
+                                &ccsupstream.object_ref(&()),
+                                CLEANUP_FAILED,
+                                ACTION_CLEANUP,
+                                err.to_string(),
+                            )
+                            .await;
+                        Err(err)
+                    }
+                },
+            }
+        },
+    )
+    .await
+    .map_err(|e| Error::FinalizerError(Box::new(e)))
+}
+
+/// Initialize the controller and shared state (waits until the crd is installed)
+pub async fn setup(
+    metrics: Arc<Metrics>,
+    health: Arc<Health>,
+    backoff: Arc<Backoff>,
+    cache: Cache,
+    selector: Option<String>,
+    settings: ReconcileSettings,
+) {
+    info!("starting the clusterconfigset controller");
+    health.register(CONTROLLER_KIND);
+    let client = connect(&health, CONTROLLER_KIND, Api::<ClusterConfigSet>::all).await;
+    let docs = Api::<ClusterConfigSet>::all(client.clone());
+    let (namespaces, namespaces_writer) = reflector::store();
+    let ctx = Arc::new(Context {
+        client: client.clone(),
+        metrics,
+        events: Events::new(client.clone()),
+        backoff,
+        cache,
+        resync_interval: settings.resync_interval,
+        dry_run: settings.dry_run,
+        namespaces: Some(namespaces.clone()),
+    });
+    info!(
+        "{}: waiting for secrets and configmaps to be listed",
+        CONTROLLER_KIND
+    );
+    ctx.cache.wait_until_synced().await;
+    let (reader, writer) = reflector::store();
+    watch_initial_sync(reader.clone(), health.clone(), CONTROLLER_KIND.to_string());
+    let stream_health = health.clone();
+    let stream = watcher(docs, watcher_config(&selector))
+        .default_backoff()
+        .reflect(writer)
+        .inspect(move |event| match event {
+            Ok(_) => stream_health.watch_succeeded(CONTROLLER_KIND),
+            Err(_) => stream_health.watch_failed(CONTROLLER_KIND),
+        })
+        .applied_objects()
+        .predicate_filter(clusterconfigset_predicate);
+    // Namespaces may start or stop matching selectors of any ClusterConfigSet,
+    // so all of them are reconciled when a namespace is changed.
+    // Only labels are needed, so namespaces are watched without their specs,
+    // and reconcilers match selectors against the store
+    let store = reader.clone();
+    let namespace_events = metadata_watcher(Api::<Namespace>::all(client), Config::default())
+        .default_backoff()
+        .reflect(namespaces_writer)
+        .touched_objects();
+    Controller::for_stream(stream, reader)
+        .watches_stream(namespace_events, move |_: PartialObjectMeta<Namespace>| {
+            store
+                .state()
+                .iter()
+                .map(|obj| ObjectRef::from_obj(obj.as_ref()))
+                .collect::<Vec<_>>()
+        })
+        .with_config(settings.controller_config())
+        .shutdown_on_signal()
+        .run(reconcile, error_policy, ctx)
+        .filter_map(|x| async move { std::result::Result::ok(x) })
+        .for_each(|_| futures::future::ready(()))
+        .await;
+    health.set_stopped(CONTROLLER_KIND);
+}
+
+/// Same as for ConfigSets, status updates must not trigger reconciliation
+fn clusterconfigset_predicate(cconfset: &ClusterConfigSet) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    cconfset.metadata.generation.hash(&mut hasher);
+    cconfset.metadata.finalizers.hash(&mut hasher);
+    cconfset
+        .metadata
+        .deletion_timestamp
+        .is_some()
+        .hash(&mut hasher);
+    is_paused(cconfset).hash(&mut hasher);
+    cconfset
+        .annotations()
+        .get(RECONCILE_AT_ANNOTATION)
+        .hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+fn error_policy(doc: Arc<ClusterConfigSet>, error: &Error, ctx: Arc<Context>) -> Action {
+    retry_action(doc.as_ref(), error, &ctx)
+}
+
+/// Get targets for every namespace that matches their selectors,
+/// namespaces are taken from the store of the namespace watcher
+async fn gather_namespaced_targets(
+    ctx: &Context,
+    targets: Vec<ClusterTargetWithName>,
+) -> Result<BTreeMap<String, Vec<TargetWithName>>> {
+    let namespaces = match &ctx.namespaces {
+        Some(store) => {
+            let _ = store.wait_until_ready().await;
+            store.state()
+        }
+        None => vec![],
+    };
+    let mut result: BTreeMap<String, Vec<TargetWithName>> = BTreeMap::new();
+    for target in targets {
+        for ns in namespaces.iter() {
+            if !target
+                .matches(ns.labels())
+                .map_err(|err| Error::IllegalConfigSet(Box::new(err)))?
+            {
+                continue;
+            }
+            result
+                .entry(ns.name_any())
+                .or_default()
+                .push(TargetWithName {
+                    name: target.name.clone(),
+                    target: target.target.clone(),
+                });
+        }
+    }
+    Ok(result)
+}
+
+/// Only templates writing to the given targets
+fn templates_for_targets(templates: &[Templates], targets: &[TargetWithName]) -> Vec<Templates> {
+    templates
+        .iter()
+        .filter(|template| targets.iter().any(|target| target.name == template.target))
+        .cloned()
+        .collect()
+}
+
+/// Result of a successful reconciliation
+#[derive(Default)]
+struct Synced {
+    /// Namespaces where targets are synced
+    namespaces: Vec<String>,
+    /// Number of targets in all the namespaces
+    targets: i64,
+    /// Descriptions of changes written to targets, prefixed with their namespaces
+    changes: Vec<String>,
+}
+
+// Changes of ConfigSets only name targets, so the namespace is added
+fn in_namespace(namespace: &str, changes: Vec<String>) -> impl Iterator<Item = String> + '_ {
+    changes
+        .into_iter()
+        .map(move |change| format!("{}: {}", namespace, change))
+}
+
+impl ClusterConfigSet {
+    // Write the result of the reconciliation to the status
+    async fn update_status(&self, ctx: Arc<Context>, result: &Result<Synced>) -> Result<()> {
+        let previous = self.status.clone().unwrap_or_default();
+        let paused = is_paused(self);
+        let conditions = vec![paused_condition(
+            paused,
+            &previous.conditions,
+            self.metadata.generation,
+        )];
+        let reconcile_at = self.annotations().get(RECONCILE_AT_ANNOTATION).cloned();
+        let status = match result {
+            Ok(_) if paused => ClusterConfigSetStatus {
+                ready: previous.ready,
+                reason: Some(PAUSED_CONDITION.to_string()),
+                message: None,
+                targets: previous.targets,
+                namespaces: previous.namespaces,
+                last_sync_time: previous.last_sync_time,
+                observed_generation: previous.observed_generation,
+                last_handled_reconcile_at: previous.last_handled_reconcile_at,
+                pending_changes: previous.pending_changes,
+                conditions,
+            },
+            Ok(synced) => ClusterConfigSetStatus {
+                ready: true,
+                reason: Some(if ctx.dry_run { "DryRun" } else { "Synced" }.to_string()),
+                message: None,
+                targets: synced.targets,
+                namespaces: synced.namespaces.clone(),
+                last_sync_time: Some(Utc::now()),
+                observed_generation: self.metadata.generation,
+                last_handled_reconcile_at: reconcile_at,
+                pending_changes: if ctx.dry_run {
+                    synced.changes.clone()
+                } else {
+                    vec![]
+                },
+                conditions,
+            },
+            Err(err) => ClusterConfigSetStatus {
+                ready: false,
+                reason: Some(err.reason()),
+                message: Some(logging::redact(&err.to_string())),
+                // Namespaces are kept, so they can still be cleaned up later
+                targets: previous.targets,
+                namespaces: previous.namespaces,
+                last_sync_time: previous.last_sync_time,
+                observed_generation: self.metadata.generation,
+                last_handled_reconcile_at: reconcile_at,
+                pending_changes: previous.pending_changes,
+                conditions,
+            },
+        };
+        let patch = json!({
+            "apiVersion": ClusterConfigSet::api_version(&()),
+            "kind": ClusterConfigSet::kind(&()),
+            "status": status,
+        });
+        let cconfsets: Api<ClusterConfigSet> = Api::all(ctx.client.clone());
+        match cconfsets
+            .patch_status(
+                self.name_any().as_str(),
+                &PatchParams::apply(SHU_FIELD_MANAGER).force(),
+                &Patch::Apply(&patch),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("{}", err);
+                Err(Error::KubeError(err))
+            }
+        }
+    }
+
+    // Reconcile (for non-finalizer related changes)
+    async fn reconcile(&self, ctx: Arc<Context>) -> Result<Synced> {
+        if let Err(err) = self.spec.validate() {
+            return Err(Error::IllegalConfigSet(Box::from(err)));
+        }
+
+        let mut inputs: HashMap<String, String> = HashMap::new();
+        for i in self.spec.inputs.clone() {
+            info!("populating data from input {}", i.name);
+            let value =
+                match get_input_value(&ctx, i.from.namespace.clone(), &i.from.to_input()).await {
+                    Ok(value) => value,
+                    Err(err) => {
+                        ctx.metrics.input_failure(CONTROLLER_KIND);
+                        ctx.events
+                            .warning(
+                                &self.object_ref(&()),
+                                INPUT_MISSING,
+                                ACTION_RECONCILE,
+                                err.to_string(),
+                            )
+                            .await;
+                        return Err(err);
+                    }
+                };
+            inputs.insert(i.name, value);
+        }
+
+        let owner_reference = build_owner_refenerce(self)?;
+        let namespaced_targets = gather_namespaced_targets(&ctx, self.spec.targets.clone()).await?;
+
+        let mut targets_count: i64 = 0;
+        let mut changes: Vec<String> = vec![];
+        for (namespace, targets) in namespaced_targets.iter() {
+            info!("syncing targets in namespace {}", namespace);
+            let (live_secrets, live_configmaps) =
+                gather_existing_targets(&ctx, namespace.clone(), targets.clone()).await?;
+            let (mut target_secrets, mut target_configmaps) = prepare_targets(
+                namespace.clone(),
+                targets.clone(),
+                owner_reference.clone(),
+                &live_secrets,
+                &live_configmaps,
+            );
+
+            if let Err(err) = build_templates(
+                templates_for_targets(&self.spec.templates, targets),
+                &mut target_secrets,
+                &mut target_configmaps,
+                targets.clone(),
+                inputs.clone(),
+                self.name_any(),
+            ) {
+                ctx.metrics.template_failure(CONTROLLER_KIND);
+                ctx.events
+                    .warning(
+                        &self.object_ref(&()),
+                        TEMPLATE_ERROR,
+                        ACTION_RECONCILE,
+                        err.to_string(),
+                    )
+                    .await;
+                return Err(err);
+            }
+
+            targets_count += (target_secrets.len() + target_configmaps.len()) as i64;
+            let written = write_targets(
+                &ctx,
+                &self.object_ref(&()),
+                namespace.clone(),
+                target_secrets,
+                target_configmaps,
+                &live_secrets,
+                &live_configmaps,
+            )
+            .await?;
+            changes.extend(in_namespace(namespace, written));
+        }
+
+        // Namespaces that were synced before, but don't match selectors anymore
+        let synced: Vec<String> = namespaced_targets.keys().cloned().collect();
+        let stale: Vec<String> = self
+            .status
+            .clone()
+            .unwrap_or_default()
+            .namespaces
+            .into_iter()
+            .filter(|namespace| !synced.contains(namespace))
+            .collect();
+        for namespace in stale {
+            info!("cleaning up targets in namespace {}", namespace);
+            let written = self
+                .cleanup_namespace(ctx.clone(), namespace.clone())
+                .await?;
+            changes.extend(in_namespace(&namespace, written));
+        }
+
+        Ok(Synced {
+            namespaces: synced,
+            targets: targets_count,
+            changes,
+        })
+    }
+
+    // Remove everything that was written to targets in the namespace,
+    // returns descriptions of changes
+    async fn cleanup_namespace(&self, ctx: Arc<Context>, namespace: String) -> Result<Vec<String>> {
+        let targets = self.spec.to_configset_spec().targets;
+        let (live_secrets, live_configmaps) =
+            gather_existing_targets(&ctx, namespace.clone(), targets.clone()).await?;
+        let (mut target_secrets, mut target_configmaps) =
+            (live_secrets.clone(), live_configmaps.clone());
+        cleanup_templates(
+            self.spec.templates.clone(),
+            &mut target_secrets,
+            &mut target_configmaps,
+            targets,
+        )?;
+        write_targets(
+            &ctx,
+            &self.object_ref(&()),
+            namespace,
+            target_secrets,
+            target_configmaps,
+            &live_secrets,
+            &live_configmaps,
+        )
+        .await
+    }
+
+    // Finalizer cleanup (the object was deleted, ensure nothing is orphaned)
+    async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action> {
+        if is_paused(self) {
+            info!(
+                "{} is paused, targets are left as they are",
+                self.name_any()
+            );
+            return Ok(Action::await_change());
+        }
+        let mut namespaces: BTreeSet<String> = self
+            .status
+            .clone()
+            .unwrap_or_default()
+            .namespaces
+            .into_iter()
+            .collect();
+        let namespaced_targets = gather_namespaced_targets(&ctx, self.spec.targets.clone()).await?;
+        namespaces.extend(namespaced_targets.into_keys());
+
+        for namespace in namespaces {
+            self.cleanup_namespace(ctx.clone(), namespace).await?;
+        }
+        Ok::<Action, Error>(Action::await_change())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::v1alpha1::clusterconfigsets_api::{
+        ClusterConfigSetSpec, ClusterInput, ClusterInputWithName,
+    };
+    use crate::api::v1alpha1::configsets_api::{Kinds, Target};
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+    use k8s_openapi::ByteString;
+    use kube::core::ObjectMeta;
+    use kube::Client;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Requests sent to the API server, with their bodies
+    type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+    // Writes are echoed, and status patches return the ClusterConfigSet
+    fn mock_client(requests: Requests) -> Client {
+        let service = tower::service_fn(move |request: http::Request<hyper::Body>| {
+            let requests = requests.clone();
+            async move {
+                let uri = request.uri().to_string();
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                requests.lock().unwrap().push((uri.clone(), body.clone()));
+                if uri.contains("/status") {
+                    let cconfset = serde_json::to_string(&clusterconfigset()).unwrap();
+                    return Ok(http::Response::new(hyper::Body::from(cconfset)));
+                }
+                Ok::<_, Infallible>(http::Response::new(hyper::Body::from(body)))
+            }
+        });
+        Client::new(service, "default")
+    }
+
+    fn mock_context(requests: Requests, namespaces: &[(&str, &str)], dry_run: bool) -> Context {
+        let client = mock_client(requests);
+        let metrics = Arc::new(Metrics::new());
+        let (reader, mut writer) = reflector::store();
+        let namespaces = namespaces
+            .iter()
+            .map(|(name, team)| PartialObjectMeta {
+                types: None,
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    labels: Some(BTreeMap::from([("team".to_string(), team.to_string())])),
+                    ..Default::default()
+                },
+                _phantom: Default::default(),
+            })
+            .collect();
+        writer.apply_watcher_event(&watcher::Event::Restarted(namespaces));
+        let database = Secret {
+            metadata: ObjectMeta {
+                name: Some("database".to_string()),
+                namespace: Some("default".to_string()),
+                resource_version: Some("1".to_string()),
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(
+                "PASSWORD".to_string(),
+                ByteString(b"qwerty".to_vec()),
+            )])),
+            ..Default::default()
+        };
+        Context {
+            client: client.clone(),
+            metrics: metrics.clone(),
+            events: Events::new(client.clone()),
+            backoff: Arc::new(Backoff::new(
+                Duration::from_secs(1),
+                Duration::from_secs(10),
+            )),
+            cache: Cache::with_objects(client, vec![database], vec![], metrics),
+            resync_interval: None,
+            dry_run,
+            namespaces: Some(reader),
+        }
+    }
+
+    fn target(team: &str) -> ClusterTargetWithName {
+        ClusterTargetWithName {
+            name: format!("{team}-app"),
+            target: Target {
+                kind: Kinds::Secret,
+                name: "app".to_string(),
+            },
+            namespace_selector: LabelSelector {
+                match_labels: Some(BTreeMap::from([("team".to_string(), team.to_string())])),
+                match_expressions: None,
+            },
+        }
+    }
+
+    fn clusterconfigset() -> ClusterConfigSet {
+        let mut cconfset = ClusterConfigSet::new(
+            "app",
+            ClusterConfigSetSpec {
+                inputs: vec![ClusterInputWithName {
+                    name: "PASSWORD".to_string(),
+                    from: ClusterInput {
+                        kind: Kinds::Secret,
+                        namespace: "default".to_string(),
+                        name: "database".to_string(),
+                        key: "PASSWORD".to_string(),
+                    },
+                }],
+                targets: vec![target("a")],
+                templates: vec![Templates {
+                    name: "CONNECTION".to_string(),
+                    template: "postgres://app:{{PASSWORD}}@db".to_string(),
+                    target: "a-app".to_string(),
+                }],
+                ..Default::default()
+            },
+        );
+        cconfset.metadata.uid = Some("0c5f1c6e".to_string());
+        cconfset
+    }
+
+    #[tokio::test]
+    async fn namespaces_are_selected_from_the_store() {
+        let requests = Requests::default();
+        let ctx = mock_context(
+            requests.clone(),
+            &[("a-dev", "a"), ("a-prod", "a"), ("b-dev", "b")],
+            false,
+        );
+        let targets = gather_namespaced_targets(&ctx, vec![target("a"), target("c")])
+            .await
+            .unwrap();
+        assert_eq!(targets.keys().collect::<Vec<_>>(), vec!["a-dev", "a-prod"]);
+        assert_eq!(targets["a-dev"][0].name, "a-app");
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dry_run_changes_are_pending_in_the_status() {
+        let requests = Requests::default();
+        let ctx = Arc::new(mock_context(
+            requests.clone(),
+            &[("a-dev", "a"), ("b-dev", "b")],
+            true,
+        ));
+        let cconfset = clusterconfigset();
+        let result = cconfset.reconcile(ctx.clone()).await;
+        cconfset.update_status(ctx, &result).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let (writes, statuses): (Vec<_>, Vec<_>) = requests
+            .iter()
+            .partition(|(uri, _)| !uri.contains("/status"));
+        assert_eq!(writes.len(), 1);
+        assert!(writes[0].0.starts_with("/api/v1/namespaces/a-dev/secrets?"));
+        assert!(writes[0].0.contains("dryRun=All"));
+        let patch: serde_json::Value = serde_json::from_str(&statuses[0].1).unwrap();
+        let status: ClusterConfigSetStatus =
+            serde_json::from_value(patch["status"].clone()).unwrap();
+        assert_eq!(status.reason.as_deref(), Some("DryRun"));
+        assert_eq!(status.namespaces, vec!["a-dev"]);
+        assert_eq!(status.pending_changes.len(), 1);
+        assert!(status.pending_changes[0].starts_with("a-dev: Secret app: "));
+        assert!(!status.pending_changes[0].contains("qwerty"));
+    }
+}
@@ -1,84 +1,168 @@
 use crate::api::v1alpha1::configsets_api::{
-    ConfigSet, Input, InputWithName, TargetWithName, Templates,
+    ConfigSet, ConfigSetStatus, Input, InputWithName, Kinds, TargetWithName, Templates,
 };
+use crate::controllers::backoff::Backoff;
+use crate::controllers::cache::{Cache, Cached};
+use crate::controllers::events::{
+    Events, ACTION_CLEANUP, ACTION_RECONCILE, CLEANUP_FAILED, INPUT_MISSING, TARGET_CREATED,
+    TARGET_UPDATED, TEMPLATE_ERROR,
+};
+use crate::controllers::ReconcileSettings;
+use crate::health::Health;
+use crate::logging::{self, LogContext};
+use crate::metrics::Metrics;
+use crate::render::render_templates;
+use crate::{Error, Result};
+use chrono::Utc;
 use core::fmt;
 use futures::StreamExt;
-use handlebars::Handlebars;
-use k8s_openapi::api::core::v1::{ConfigMap, Secret};
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
-use k8s_openapi::{ByteString, NamespaceResourceScope};
-use kube::api::{ListParams, PostParams};
-use kube::core::{Object, ObjectMeta};
-use kube::error::ErrorResponse;
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace, ObjectReference, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, OwnerReference, Time};
+use k8s_openapi::ByteString;
+use kube::api::{ListParams, Patch, PatchParams, PostParams};
+use kube::core::{ObjectMeta, PartialObjectMeta};
 use kube::runtime::controller::Action;
 use kube::runtime::finalizer::Event as Finalizer;
+use kube::runtime::reflector::{ObjectRef, Store};
 use kube::runtime::watcher::Config;
-use kube::runtime::{finalizer, Controller};
-use kube::{Api, Client, CustomResource};
-use kube_client::core::DynamicObject;
+use kube::runtime::{finalizer, reflector, watcher, Controller, WatchStreamExt};
+use kube::{Api, Client};
 use kube_client::{Resource, ResourceExt};
 use log::*;
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, HashMap};
-use std::str::{from_utf8, Utf8Error};
+use std::hash::{Hash, Hasher};
+use std::str::from_utf8;
 use std::sync::Arc;
 use std::time::Duration;
-use thiserror::Error;
+use tracing::{field, info_span, instrument, Instrument, Span};
 
 static WATCHED_BY_SHU: &str = "badhouseplants.net/watched-by-shu";
-static SHU_FINALIZER: &str = "badhouseplants.net/shu-cleanup";
-
-#[derive(Error, Debug)]
-pub enum Error {
-    #[error("Kube Error: {0}")]
-    KubeError(#[source] kube::Error),
-
-    #[error("Finalizer Error: {0}")]
-    // NB: awkward type because finalizer::Error embeds the reconciler error (which is this)
-    // so boxing this error to break cycles
-    FinalizerError(#[source] Box<kube::runtime::finalizer::Error<Error>>),
-
-    #[error("IllegalConfigSet: {0}")]
-    IllegalConfigSet(#[source] Box<dyn std::error::Error + Send + Sync>),
-}
-
-pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+static SHU_CONTENT_HASH: &str = "badhouseplants.net/shu-content-hash";
+/// Targets are not written while the annotation is set to "true",
+/// they are not cleaned up either when the ConfigSet is deleted
+pub static PAUSED_ANNOTATION: &str = "shoebill.badhouseplants.net/paused";
+/// Changing the annotation forces a reconciliation, e.g. when it's set to the current time
+pub static RECONCILE_AT_ANNOTATION: &str = "shoebill.badhouseplants.net/reconcile-at";
+pub(crate) static PAUSED_CONDITION: &str = "Paused";
+pub(crate) static SHU_FINALIZER: &str = "badhouseplants.net/shu-cleanup";
+pub(crate) static SHU_FIELD_MANAGER: &str = "shoebill";
+/// Kind of objects handled by the controller, used in metrics and health checks
+static CONTROLLER_KIND: &str = "configset";
+static CONNECT_RETRY_INTERVAL: Duration = Duration::from_secs(10);
 
 // Context for our reconciler
 #[derive(Clone)]
 pub struct Context {
     /// Kubernetes client
     pub client: Client,
+    /// Prometheus metrics
+    pub(crate) metrics: Arc<Metrics>,
+    /// Kubernetes events publisher
+    pub(crate) events: Events,
+    /// Delays before retrying failed objects
+    pub(crate) backoff: Arc<Backoff>,
+    /// Shared Secrets and ConfigMaps
+    pub(crate) cache: Cache,
+    /// Reconcile objects again after the interval, even if nothing has changed
+    pub(crate) resync_interval: Option<Duration>,
+    /// Only validate writes to targets by the API server, without persisting them
+    pub(crate) dry_run: bool,
+    /// Labels of namespaces, only watched by the ClusterConfigSet controller
+    pub(crate) namespaces: Option<Store<PartialObjectMeta<Namespace>>>,
+}
+
+impl Context {
+    /// Objects are reconciled again after their own refresh interval or after
+    /// the global resync interval, so changes that are not watched are picked up
+    pub(crate) fn success_action(&self, refresh_interval: Option<Duration>) -> Action {
+        match refresh_interval.or(self.resync_interval) {
+            Some(interval) => Action::requeue(interval),
+            None => Action::await_change(),
+        }
+    }
 }
 
 async fn reconcile(csupstream: Arc<ConfigSet>, ctx: Arc<Context>) -> Result<Action> {
-    let ns = csupstream.namespace().unwrap();
+    let context = LogContext::new(csupstream.name_any(), csupstream.namespace());
+    let span = info_span!(
+        "reconcile",
+        configset = %csupstream.name_any(),
+        namespace = csupstream.namespace(),
+        reconcile_id = context.reconcile_id(),
+    );
+    // Errors are handled outside of the scope, so they are redacted while values are known
+    let reconcile = async {
+        reconcile_configset(csupstream, ctx)
+            .await
+            .map_err(Error::redacted)
+    };
+    logging::scope(context, reconcile).instrument(span).await
+}
+
+async fn reconcile_configset(csupstream: Arc<ConfigSet>, ctx: Arc<Context>) -> Result<Action> {
+    let ns = object_namespace(csupstream.as_ref())?;
     let confset: Api<ConfigSet> = Api::namespaced(ctx.client.clone(), &ns);
+    let _timer = ctx.metrics.reconcile_timer(CONTROLLER_KIND);
     finalizer(&confset, SHU_FINALIZER, csupstream.clone(), |event| async {
-        info!(
-            "reconciling {} - {}",
-            csupstream.metadata.name.clone().unwrap(),
-            csupstream.metadata.namespace.clone().unwrap()
-        );
+        info!("reconciling {} - {}", csupstream.name_any(), ns);
         match event {
-            Finalizer::Apply(doc) => match csupstream.reconcile(ctx.clone()).await {
-                Ok(res) => {
-                    info!("reconciled successfully");
-                    Ok(res)
-                }
-                Err(err) => {
-                    error!("reconciliation has failed with error: {}", err);
-                    Err(err)
-                }
-            },
-            Finalizer::Cleanup(doc) => match csupstream.cleanup(ctx.clone()).await {
+            // Nothing is written while paused, the status only shows that it's paused
+            Finalizer::Apply(_doc) if is_paused(csupstream.as_ref()) => {
+                info!("{} is paused", csupstream.name_any());
+                csupstream.update_status(ctx.clone(), &Ok(vec![])).await?;
+                Ok(Action::await_change())
+            }
+            Finalizer::Apply(_doc) => {
+                let res = csupstream.reconcile(ctx.clone()).await;
+                match &res {
+                    Ok(_) => {
+                        info!("reconciled successfully");
+                        ctx.backoff
+                            .reset(&ObjectRef::from_obj(csupstream.as_ref()).to_string());
+                        ctx.metrics.reconcile_success(CONTROLLER_KIND);
+                        ctx.metrics.set_targets(
+                            CONTROLLER_KIND,
+                            ObjectRef::from_obj(csupstream.as_ref()).to_string(),
+                            csupstream.spec.targets.len() as i64,
+                        );
+                    }
+                    Err(err) => {
+                        error!("reconciliation has failed with error: {}", err);
+                        ctx.metrics
+                            .reconcile_failure(CONTROLLER_KIND, &err.metric_label());
+                    }
+                };
+                csupstream.update_status(ctx.clone(), &res).await?;
+                res.map(|_| ctx.success_action(csupstream.spec.refresh_duration()))
+            }
+            Finalizer::Cleanup(_doc) => match csupstream.cleanup(ctx.clone()).await {
                 Ok(res) => {
                     info!("cleaned up successfully");
+                    ctx.backoff
+                        .reset(&ObjectRef::from_obj(csupstream.as_ref()).to_string());
+                    ctx.metrics.remove_object(
+                        CONTROLLER_KIND,
+                        &ObjectRef::from_obj(csupstream.as_ref()).to_string(),
+                    );
                     Ok(res)
                 }
                 Err(err) => {
                     error!("cleanup has failed with error: {}", err);
+                    ctx.metrics
+                        .reconcile_failure(CONTROLLER_KIND, &err.metric_label());
+                    ctx.events
+                        .warning(
+                            &csupstream.object_ref(&()),
+                            CLEANUP_FAILED,
+                            ACTION_CLEANUP,
+                            err.to_string(),
+                        )
+                        .await;
                     Err(err)
                 }
             },
@@ -88,196 +172,444 @@ async fn reconcile(csupstream: Arc<ConfigSet>, ctx: Arc<Context>) -> Result<Acti
     .map_err(|e| Error::FinalizerError(Box::new(e)))
 }
 
-/// Initialize the controller and shared state (given the crd is installed)
-pub async fn setup() {
+/// Initialize the controller and shared state (waits until the crd is installed).
+/// When namespaces are set, ConfigSets are only watched in them, and nothing
+/// outside of them is read or written
+pub async fn setup(
+    metrics: Arc<Metrics>,
+    health: Arc<Health>,
+    backoff: Arc<Backoff>,
+    cache: Cache,
+    namespaces: Vec<String>,
+    selector: Option<String>,
+    settings: ReconcileSettings,
+) {
     info!("starting the configset controller");
-    let client = Client::try_default()
-        .await
-        .expect("failed to create kube Client");
-    let docs = Api::<ConfigSet>::all(client.clone());
-    if let Err(e) = docs.list(&ListParams::default().limit(1)).await {
-        error!("{}", e);
-        std::process::exit(1);
-    }
-    let ctx = Arc::new(Context { client });
-    Controller::new(docs, Config::default().any_semantic())
+    if namespaces.is_empty() {
+        run(metrics, health, backoff, cache, None, selector, settings).await;
+        return;
+    }
+    // Every namespace gets its own controller, so it doesn't need
+    // permissions to list ConfigSets in the whole cluster
+    futures::future::join_all(namespaces.into_iter().map(|namespace| {
+        run(
+            metrics.clone(),
+            health.clone(),
+            backoff.clone(),
+            cache.clone(),
+            Some(namespace),
+            selector.clone(),
+            settings,
+        )
+    }))
+    .await;
+}
+
+async fn run(
+    metrics: Arc<Metrics>,
+    health: Arc<Health>,
+    backoff: Arc<Backoff>,
+    cache: Cache,
+    namespace: Option<String>,
+    selector: Option<String>,
+    settings: ReconcileSettings,
+) {
+    let health_key = match &namespace {
+        Some(namespace) => format!("{}/{}", CONTROLLER_KIND, namespace),
+        None => CONTROLLER_KIND.to_string(),
+    };
+    health.register(&health_key);
+    let make_api = |client: Client| match &namespace {
+        Some(namespace) => Api::<ConfigSet>::namespaced(client, namespace),
+        None => Api::<ConfigSet>::all(client),
+    };
+    let client = connect(&health, &health_key, make_api).await;
+    let docs = make_api(client.clone());
+    let ctx = Arc::new(Context {
+        client: client.clone(),
+        metrics,
+        events: Events::new(client),
+        backoff,
+        cache,
+        resync_interval: settings.resync_interval,
+        dry_run: settings.dry_run,
+        namespaces: None,
+    });
+    // Reconciles only start once inputs and targets are listed,
+    // otherwise each of them would be read from the API
+    info!(
+        "{}: waiting for secrets and configmaps to be listed",
+        health_key
+    );
+    ctx.cache.wait_until_synced().await;
+    let (reader, writer) = reflector::store();
+    watch_initial_sync(reader.clone(), health.clone(), health_key.clone());
+    let stream_health = health.clone();
+    let stream_key = health_key.clone();
+    let stream = watcher(docs, watcher_config(&selector))
+        .default_backoff()
+        .reflect(writer)
+        .inspect(move |event| match event {
+            Ok(_) => stream_health.watch_succeeded(&stream_key),
+            Err(_) => stream_health.watch_failed(&stream_key),
+        })
+        .applied_objects()
+        .predicate_filter(configset_predicate);
+    Controller::for_stream(stream, reader)
+        .with_config(settings.controller_config())
         .shutdown_on_signal()
         .run(reconcile, error_policy, ctx)
         .filter_map(|x| async move { std::result::Result::ok(x) })
         .for_each(|_| futures::future::ready(()))
         .await;
+    health.set_stopped(&health_key);
 }
 
-fn error_policy(doc: Arc<ConfigSet>, error: &Error, ctx: Arc<Context>) -> Action {
-    Action::requeue(Duration::from_secs(5 * 60))
+/// Only objects matching the label selector are watched, if it's set
+pub(crate) fn watcher_config(selector: &Option<String>) -> Config {
+    let config = Config::default().any_semantic();
+    match selector {
+        Some(selector) => config.labels(selector),
+        None => config,
+    }
 }
 
-fn get_secret_api(client: Client, namespace: String) -> Api<Secret> {
-    Api::namespaced(client, &namespace)
+/// Wait until the API server is reachable and the CRD of K is installed.
+/// It's retried instead of exiting, so the state is visible on /readyz
+pub(crate) async fn connect<K>(
+    health: &Health,
+    health_key: &str,
+    make_api: impl Fn(Client) -> Api<K>,
+) -> Client
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + fmt::Debug,
+{
+    loop {
+        let client = match Client::try_default().await {
+            Ok(client) => client,
+            Err(err) => {
+                error!("failed to create kube client: {}", err);
+                health.set_client_connected(health_key, false);
+                tokio::time::sleep(CONNECT_RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+        health.set_client_connected(health_key, true);
+        match make_api(client.clone())
+            .list(&ListParams::default().limit(1))
+            .await
+        {
+            Ok(_) => {
+                health.set_crds_installed(health_key, true);
+                return client;
+            }
+            Err(err) => {
+                error!("{} crd is not available: {}", health_key, err);
+                health.set_crds_installed(health_key, false);
+                tokio::time::sleep(CONNECT_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Mark the controller as synced once the store got the initial list of objects
+pub(crate) fn watch_initial_sync<K>(store: Store<K>, health: Arc<Health>, health_key: String)
+where
+    K: Resource<DynamicType = ()> + Clone + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        if store.wait_until_ready().await.is_ok() {
+            info!("{} watcher has synced", health_key);
+            health.set_synced(&health_key);
+        }
+    });
+}
+
+/// Only changes of the spec, finalizers and deletion should trigger reconciliation,
+/// otherwise every status update would cause one more reconciliation
+fn configset_predicate(confset: &ConfigSet) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    confset.metadata.generation.hash(&mut hasher);
+    confset.metadata.finalizers.hash(&mut hasher);
+    confset
+        .metadata
+        .deletion_timestamp
+        .is_some()
+        .hash(&mut hasher);
+    is_paused(confset).hash(&mut hasher);
+    confset
+        .annotations()
+        .get(RECONCILE_AT_ANNOTATION)
+        .hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+pub(crate) fn is_paused<K: ResourceExt>(obj: &K) -> bool {
+    obj.annotations()
+        .get(PAUSED_ANNOTATION)
+        .is_some_and(|value| value == "true")
+}
+
+/// The transition time is only changed when the status of the condition is changed
+pub(crate) fn paused_condition(
+    paused: bool,
+    previous: &[Condition],
+    generation: Option<i64>,
+) -> Condition {
+    let status = if paused { "True" } else { "False" }.to_string();
+    let last_transition_time = previous
+        .iter()
+        .find(|condition| condition.type_ == PAUSED_CONDITION && condition.status == status)
+        .map(|condition| condition.last_transition_time.clone())
+        .unwrap_or_else(|| Time(Utc::now()));
+    Condition {
+        type_: PAUSED_CONDITION.to_string(),
+        status,
+        reason: if paused {
+            "PausedByAnnotation"
+        } else {
+            "Active"
+        }
+        .to_string(),
+        message: if paused {
+            format!(
+                "{} annotation is set, targets are not written",
+                PAUSED_ANNOTATION
+            )
+        } else {
+            String::new()
+        },
+        last_transition_time,
+        observed_generation: generation,
+    }
+}
+
+fn error_policy(doc: Arc<ConfigSet>, error: &Error, ctx: Arc<Context>) -> Action {
+    retry_action(doc.as_ref(), error, &ctx)
 }
 
-fn get_configmap_api(client: Client, namespace: String) -> Api<ConfigMap> {
-    Api::namespaced(client, &namespace)
+/// Retry transient errors with backoff, and wait for the spec
+/// to be changed when the object itself is broken
+pub(crate) fn retry_action<K: Resource<DynamicType = ()>>(
+    object: &K,
+    error: &Error,
+    ctx: &Context,
+) -> Action {
+    let object = ObjectRef::from_obj(object).to_string();
+    if !error.is_transient() {
+        info!("{} will be reconciled when its spec is changed", object);
+        ctx.backoff.reset(&object);
+        return Action::await_change();
+    }
+    let delay = ctx.backoff.next_delay(&object);
+    info!("{} will be retried in {}s", object, delay.as_secs());
+    Action::requeue(delay)
 }
 
-async fn gather_inputs(
-    client: Client,
+#[instrument(skip_all, fields(inputs = inputs.len()))]
+pub(crate) async fn gather_inputs(
+    ctx: &Context,
     namespace: String,
     inputs: Vec<InputWithName>,
 ) -> Result<HashMap<String, String>> {
     let mut result: HashMap<String, String> = HashMap::new();
     for i in inputs {
         info!("populating data from input {}", i.name);
-        match i.from.kind {
-            crate::api::v1alpha1::configsets_api::Kinds::Secret => {
-                let secret: String = match get_secret_api(client.clone(), namespace.clone())
-                    .get(&i.from.name)
-                    .await
-                {
-                    Ok(s) => {
-                        let data = s.data.clone().unwrap();
-                        let value = match data.get(i.from.key.as_str()) {
-                            Some(data) => match from_utf8(&data.0) {
-                                Ok(data) => data,
-                                Err(err) => return Err(Error::IllegalConfigSet(Box::from(err))),
-                            },
-                            None => {
-                                return Err(Error::IllegalConfigSet(Box::from(format!(
-                                    "value is not set for the key: {}",
-                                    i.from.key
-                                ))))
-                            }
-                        };
-                        value.to_string()
-                    }
-                    Err(err) => {
-                        error!("{err}");
-                        return Err(Error::KubeError(err));
-                    }
-                };
-                result.insert(i.from.key, secret);
+        let value = get_input_value(ctx, namespace.clone(), &i.from).await?;
+        result.insert(i.name, value);
+    }
+    Ok(result)
+}
+
+/// Get the value of a single input from the Secret or ConfigMap in the namespace
+#[instrument(skip_all, fields(kind = ?input.kind, name = %input.name))]
+pub(crate) async fn get_input_value(
+    ctx: &Context,
+    namespace: String,
+    input: &Input,
+) -> Result<String> {
+    let value = match input.kind {
+        Kinds::Secret => match ctx.cache.get_secret(&namespace, &input.name).await? {
+            Some(secret) => secret_input_value(&secret, input),
+            None => Err(input_not_found(input)),
+        },
+        Kinds::ConfigMap => match ctx.cache.get_configmap(&namespace, &input.name).await? {
+            Some(configmap) => configmap_input_value(&configmap, input),
+            None => Err(input_not_found(input)),
+        },
+    }?;
+    logging::add_sensitive([&value]);
+    Ok(value)
+}
+
+pub(crate) fn input_not_found(input: &Input) -> Error {
+    Error::InputNotFound {
+        kind: format!("{:?}", input.kind),
+        name: input.name.clone(),
+    }
+}
+
+fn key_not_found(input: &Input) -> Error {
+    Error::KeyNotFound {
+        kind: format!("{:?}", input.kind),
+        name: input.name.clone(),
+        key: input.key.clone(),
+    }
+}
+
+fn decode_input_value(input: &Input, value: &[u8]) -> Result<String> {
+    match from_utf8(value) {
+        Ok(value) => Ok(value.to_string()),
+        Err(err) => Err(Error::InvalidUtf8 {
+            kind: format!("{:?}", input.kind),
+            name: input.name.clone(),
+            key: input.key.clone(),
+            source: err,
+        }),
+    }
+}
+
+/// Read the input key from the Secret, it may be set in either data or stringData
+pub(crate) fn secret_input_value(secret: &Secret, input: &Input) -> Result<String> {
+    if let Some(value) = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get(input.key.as_str()))
+    {
+        return decode_input_value(input, &value.0);
+    }
+    match secret
+        .string_data
+        .as_ref()
+        .and_then(|data| data.get(input.key.as_str()))
+    {
+        Some(value) => Ok(value.clone()),
+        None => Err(key_not_found(input)),
+    }
+}
+
+/// Read the input key from the ConfigMap, it may be set in either data or binaryData
+pub(crate) fn configmap_input_value(configmap: &ConfigMap, input: &Input) -> Result<String> {
+    if let Some(value) = configmap
+        .data
+        .as_ref()
+        .and_then(|data| data.get(input.key.as_str()))
+    {
+        return Ok(value.clone());
+    }
+    match configmap
+        .binary_data
+        .as_ref()
+        .and_then(|data| data.get(input.key.as_str()))
+    {
+        Some(value) => decode_input_value(input, &value.0),
+        None => Err(key_not_found(input)),
+    }
+}
+
+/// Namespace of the object, only namespaced objects are expected
+pub(crate) fn object_namespace<K: Resource>(object: &K) -> Result<String> {
+    match object.meta().namespace.clone() {
+        Some(namespace) => Ok(namespace),
+        None => Err(Error::MissingMetadata("namespace".to_string())),
+    }
+}
+
+/// Take existing targets, missing ones are prepared in memory.
+/// They are created together with their data when they are written
+pub(crate) fn prepare_targets(
+    namespace: String,
+    targets: Vec<TargetWithName>,
+    owner_reference: Vec<OwnerReference>,
+    live_secrets: &HashMap<String, Secret>,
+    live_configmaps: &HashMap<String, ConfigMap>,
+) -> (HashMap<String, Secret>, HashMap<String, ConfigMap>) {
+    let mut target_secrets = live_secrets.clone();
+    let mut target_configmaps = live_configmaps.clone();
+    for target in targets {
+        let metadata = ObjectMeta {
+            name: Some(target.target.name.clone()),
+            namespace: Some(namespace.clone()),
+            owner_references: Some(owner_reference.clone()),
+            ..Default::default()
+        };
+        match target.target.kind {
+            Kinds::Secret => {
+                target_secrets.entry(target.name).or_insert_with(|| Secret {
+                    data: Some(BTreeMap::new()),
+                    metadata,
+                    ..Default::default()
+                });
             }
-            crate::api::v1alpha1::configsets_api::Kinds::ConfigMap => {
-                let configmap: String = match get_configmap_api(client.clone(), namespace.clone())
-                    .get(&i.from.name)
-                    .await
-                {
-                    Ok(cm) => {
-                        let data = cm.data.unwrap();
-                        let value = match data.get(i.from.key.as_str()) {
-                            Some(data) => data,
-                            None => {
-                                return Err(Error::IllegalConfigSet(Box::from(format!(
-                                    "value is not set for the key: {}",
-                                    i.from.key
-                                ))))
-                            }
-                        };
-                        value.to_string()
-                    }
-                    Err(err) => {
-                        error!("{err}");
-                        return Err(Error::KubeError(err));
-                    }
-                };
-                result.insert(i.name, configmap);
+            Kinds::ConfigMap => {
+                target_configmaps
+                    .entry(target.name)
+                    .or_insert_with(|| ConfigMap {
+                        data: Some(BTreeMap::new()),
+                        metadata,
+                        ..Default::default()
+                    });
             }
         }
     }
-    Ok(result)
+    (target_secrets, target_configmaps)
 }
 
-async fn gather_targets(
-    client: Client,
+/// Get targets that exist already, without creating missing ones
+#[instrument(skip_all, fields(targets = targets.len()))]
+pub(crate) async fn gather_existing_targets(
+    ctx: &Context,
     namespace: String,
     targets: Vec<TargetWithName>,
-    owner_reference: Vec<OwnerReference>,
 ) -> Result<(HashMap<String, Secret>, HashMap<String, ConfigMap>)> {
     let mut target_secrets: HashMap<String, Secret> = HashMap::new();
     let mut target_configmaps: HashMap<String, ConfigMap> = HashMap::new();
     for target in targets {
         match target.target.kind {
-            crate::api::v1alpha1::configsets_api::Kinds::Secret => {
-                let api = get_secret_api(client.clone(), namespace.clone());
-                match api.get_opt(&target.target.name).await {
-                    Ok(sec_opt) => match sec_opt {
-                        Some(sec) => target_secrets.insert(target.name, sec),
-                        None => {
-                            let empty_data: BTreeMap<String, ByteString> = BTreeMap::new();
-                            let new_secret: Secret = Secret {
-                                data: Some(empty_data),
-                                metadata: ObjectMeta {
-                                    name: Some(target.target.name),
-                                    namespace: Some(namespace.clone()),
-                                    owner_references: Some(owner_reference.clone()),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            };
-                            match api.create(&PostParams::default(), &new_secret).await {
-                                Ok(sec) => target_secrets.insert(target.name, sec),
-                                Err(err) => {
-                                    error!("{err}");
-                                    return Err(Error::KubeError(err));
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        error!("{err}");
-                        return Err(Error::KubeError(err));
-                    }
-                };
+            Kinds::Secret => {
+                if let Some(sec) = ctx
+                    .cache
+                    .get_secret(&namespace, &target.target.name)
+                    .await?
+                {
+                    target_secrets.insert(target.name, sec);
+                }
             }
-            crate::api::v1alpha1::configsets_api::Kinds::ConfigMap => {
-                let api = get_configmap_api(client.clone(), namespace.clone());
-                match api.get_opt(&target.target.name).await {
-                    Ok(cm_opt) => match cm_opt {
-                        Some(cm) => target_configmaps.insert(target.name, cm),
-                        None => {
-                            let empty_data: BTreeMap<String, String> = BTreeMap::new();
-                            let new_configmap: ConfigMap = ConfigMap {
-                                data: Some(empty_data),
-                                metadata: ObjectMeta {
-                                    name: Some(target.target.name),
-                                    namespace: Some(namespace.clone()),
-                                    owner_references: Some(owner_reference.clone()),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            };
-                            match api.create(&PostParams::default(), &new_configmap).await {
-                                Ok(cm) => target_configmaps.insert(target.name, cm),
-                                Err(err) => {
-                                    error!("{err}");
-                                    return Err(Error::KubeError(err));
-                                }
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        error!("{err}");
-                        return Err(Error::KubeError(err));
-                    }
-                };
+            Kinds::ConfigMap => {
+                if let Some(cm) = ctx
+                    .cache
+                    .get_configmap(&namespace, &target.target.name)
+                    .await?
+                {
+                    target_configmaps.insert(target.name, cm);
+                }
             }
         }
     }
     Ok((target_secrets, target_configmaps))
 }
 
-fn build_owner_refenerce(object: ConfigSet) -> Vec<OwnerReference> {
+pub(crate) fn build_owner_refenerce<K: Resource<DynamicType = ()>>(
+    object: &K,
+) -> Result<Vec<OwnerReference>> {
+    let Some(name) = object.meta().name.clone() else {
+        return Err(Error::MissingMetadata("name".to_string()));
+    };
+    let Some(uid) = object.meta().uid.clone() else {
+        return Err(Error::MissingMetadata("uid".to_string()));
+    };
     let owner_reference = OwnerReference {
-        api_version: ConfigSet::api_version(&()).to_string(),
-        kind: ConfigSet::kind(&()).to_string(),
-        name: object.metadata.name.unwrap(),
-        uid: object.metadata.uid.unwrap(),
+        api_version: K::api_version(&()).to_string(),
+        kind: K::kind(&()).to_string(),
+        name,
+        uid,
         ..Default::default()
     };
-    vec![owner_reference]
+    Ok(vec![owner_reference])
 }
 
-fn build_templates(
+#[instrument(skip_all, fields(templates = templates.len()))]
+pub(crate) fn build_templates(
     templates: Vec<Templates>,
     target_secrets: &mut HashMap<String, Secret>,
     target_configmaps: &mut HashMap<String, ConfigMap>,
@@ -285,55 +617,33 @@ fn build_templates(
     inputs: HashMap<String, String>,
     confset_name: String,
 ) -> Result<()> {
-    for template in templates {
-        let reg = Handlebars::new();
-        info!("building template {}", template.name);
-        let var = match reg.render_template(template.template.as_str(), &inputs) {
-            Ok(var) => var,
-            Err(err) => return Err(Error::IllegalConfigSet(Box::from(err))),
-        };
-
-        let target = match targets.iter().find(|target| target.name == template.target) {
-            Some(target) => target,
-            None => {
-                return Err(Error::IllegalConfigSet(Box::from(format!(
-                    "target not found {}",
-                    template.target
-                ))));
-            }
-        };
-
-        match target.target.kind {
-            crate::api::v1alpha1::configsets_api::Kinds::Secret => {
-                let sec = target_secrets.get_mut(&template.target).unwrap();
-                let mut byte_var: ByteString = ByteString::default();
-                byte_var.0 = var.as_bytes().to_vec();
-
-                let mut existing_data = match sec.clone().data {
-                    Some(sec) => sec,
-                    None => BTreeMap::new(),
+    let rendered = render_templates(&templates, &targets, &inputs)?;
+    for (target_name, target) in rendered {
+        if target.data.is_empty() {
+            continue;
+        }
+        match target.kind {
+            Kinds::Secret => {
+                let Some(sec) = target_secrets.get_mut(&target_name) else {
+                    return Err(Error::TargetMissing(target_name));
                 };
-                existing_data.insert(template.name, byte_var);
+                let mut existing_data = sec.clone().data.unwrap_or_default();
+                for (key, value) in target.data {
+                    existing_data.insert(key, ByteString(value.into_bytes()));
+                }
                 sec.data = Some(existing_data);
-                let mut existing_annotations = match sec.metadata.annotations.clone() {
-                    Some(ann) => ann,
-                    None => BTreeMap::new(),
-                };
+                let mut existing_annotations = sec.metadata.annotations.clone().unwrap_or_default();
                 existing_annotations.insert(WATCHED_BY_SHU.to_string(), confset_name.clone());
                 sec.metadata.annotations = Some(existing_annotations);
             }
-            crate::api::v1alpha1::configsets_api::Kinds::ConfigMap => {
-                let cm = target_configmaps.get_mut(&template.target).unwrap();
-                let mut existing_data = match cm.clone().data {
-                    Some(cm) => cm,
-                    None => BTreeMap::new(),
+            Kinds::ConfigMap => {
+                let Some(cm) = target_configmaps.get_mut(&target_name) else {
+                    return Err(Error::TargetMissing(target_name));
                 };
-                existing_data.insert(template.name, var);
+                let mut existing_data = cm.clone().data.unwrap_or_default();
+                existing_data.extend(target.data);
                 cm.data = Some(existing_data);
-                let mut existing_annotations = match cm.metadata.annotations.clone() {
-                    Some(ann) => ann,
-                    None => BTreeMap::new(),
-                };
+                let mut existing_annotations = cm.metadata.annotations.clone().unwrap_or_default();
                 existing_annotations.insert(WATCHED_BY_SHU.to_string(), confset_name.clone());
                 cm.metadata.annotations = Some(existing_annotations);
             }
@@ -342,7 +652,7 @@ fn build_templates(
     Ok(())
 }
 
-fn cleanup_templates(
+pub(crate) fn cleanup_templates(
     templates: Vec<Templates>,
     target_secrets: &mut HashMap<String, Secret>,
     target_configmaps: &mut HashMap<String, ConfigMap>,
@@ -352,17 +662,15 @@ fn cleanup_templates(
         info!("cleaning template {}", template.name);
         let target = match targets.iter().find(|target| target.name == template.target) {
             Some(target) => target,
-            None => {
-                return Err(Error::IllegalConfigSet(Box::from(format!(
-                    "target not found {}",
-                    template.target
-                ))));
-            }
+            None => return Err(Error::TargetMissing(template.target.clone())),
         };
 
         match target.target.kind {
-            crate::api::v1alpha1::configsets_api::Kinds::Secret => {
-                let sec = target_secrets.get_mut(&template.target).unwrap();
+            Kinds::Secret => {
+                // Targets that don't exist have nothing to clean up
+                let Some(sec) = target_secrets.get_mut(&template.target) else {
+                    continue;
+                };
                 if let Some(mut existing_data) = sec.clone().data {
                     existing_data.remove(&template.name);
                     sec.data = Some(existing_data)
@@ -372,8 +680,10 @@ fn cleanup_templates(
                     sec.metadata.annotations = Some(existing_annotations);
                 }
             }
-            crate::api::v1alpha1::configsets_api::Kinds::ConfigMap => {
-                let cm = target_configmaps.get_mut(&template.target).unwrap();
+            Kinds::ConfigMap => {
+                let Some(cm) = target_configmaps.get_mut(&template.target) else {
+                    continue;
+                };
                 if let Some(mut existing_data) = cm.clone().data {
                     existing_data.remove(&template.name);
                     cm.data = Some(existing_data);
@@ -388,102 +698,365 @@ fn cleanup_templates(
     Ok(())
 }
 
+/// Data of a target by keys, values of ConfigMaps are compared as bytes
+pub(crate) type TargetData<'a> = BTreeMap<&'a str, &'a [u8]>;
+
+pub(crate) fn secret_data(secret: &Secret) -> TargetData<'_> {
+    secret
+        .data
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value.0.as_slice()))
+        .collect()
+}
+
+pub(crate) fn configmap_data(configmap: &ConfigMap) -> TargetData<'_> {
+    configmap
+        .data
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value.as_bytes()))
+        .chain(
+            configmap
+                .binary_data
+                .iter()
+                .flatten()
+                .map(|(key, value)| (key.as_str(), value.0.as_slice())),
+        )
+        .collect()
+}
+
+/// Hash of the data and annotations of a target, the hash annotation itself is ignored
+fn content_hash(data: &TargetData, annotations: &Option<BTreeMap<String, String>>) -> String {
+    let mut hasher = Sha256::new();
+    for (key, value) in data {
+        hasher.update(key.as_bytes());
+        hasher.update([0]);
+        hasher.update(value);
+        hasher.update([0]);
+    }
+    hasher.update([0]);
+    for (key, value) in annotations.iter().flatten() {
+        if key != SHU_CONTENT_HASH {
+            hasher.update(key.as_bytes());
+            hasher.update([0]);
+            hasher.update(value.as_bytes());
+            hasher.update([0]);
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Describe the change by keys only, so values of Secrets never get to logs
+fn data_diff(live: Option<&TargetData>, desired: &TargetData) -> String {
+    let live = match live {
+        Some(live) => live,
+        None => {
+            let keys: Vec<&str> = desired.keys().copied().collect();
+            return format!("created with keys [{}]", keys.join(", "));
+        }
+    };
+    let added: Vec<&str> = desired
+        .keys()
+        .filter(|key| !live.contains_key(*key))
+        .copied()
+        .collect();
+    let changed: Vec<&str> = desired
+        .iter()
+        .filter(|(key, value)| live.get(*key).is_some_and(|live| live != *value))
+        .map(|(key, _)| *key)
+        .collect();
+    let removed: Vec<&str> = live
+        .keys()
+        .filter(|key| !desired.contains_key(*key))
+        .copied()
+        .collect();
+    let mut diff: Vec<String> = vec![];
+    for (verb, keys) in [("added", added), ("changed", changed), ("removed", removed)] {
+        if !keys.is_empty() {
+            diff.push(format!("{} [{}]", verb, keys.join(", ")));
+        }
+    }
+    if diff.is_empty() {
+        "annotations changed".to_string()
+    } else {
+        diff.join(", ")
+    }
+}
+
+// The hash is only recorded on targets that are managed by shoebill
+fn set_content_hash(metadata: &mut ObjectMeta, hash: String) {
+    let mut annotations = metadata.annotations.clone().unwrap_or_default();
+    if annotations.contains_key(WATCHED_BY_SHU) {
+        annotations.insert(SHU_CONTENT_HASH.to_string(), hash);
+    } else {
+        annotations.remove(SHU_CONTENT_HASH);
+    }
+    metadata.annotations = Some(annotations);
+}
+
+/// Write the rendered targets to the cluster.
+/// Targets are compared with live objects, that were gathered before rendering,
+/// by their content hashes, and only changed ones are written.
+/// Returns descriptions of changes
+pub(crate) async fn write_targets(
+    ctx: &Context,
+    owner: &ObjectReference,
+    namespace: String,
+    target_secrets: HashMap<String, Secret>,
+    target_configmaps: HashMap<String, ConfigMap>,
+    live_secrets: &HashMap<String, Secret>,
+    live_configmaps: &HashMap<String, ConfigMap>,
+) -> Result<Vec<String>> {
+    let mut changes: Vec<String> = vec![];
+    for (name, value) in target_secrets {
+        let live = live_secrets.get(&name);
+        let target = format!("Secret/{}", value.name_any());
+        let write = write_target(ctx, owner, &namespace, live, value, secret_data);
+        changes.extend(logging::with_target(target, write).await?);
+    }
+    for (name, value) in target_configmaps {
+        let live = live_configmaps.get(&name);
+        let target = format!("ConfigMap/{}", value.name_any());
+        let write = write_target(ctx, owner, &namespace, live, value, configmap_data);
+        changes.extend(logging::with_target(target, write).await?);
+    }
+    Ok(changes)
+}
+
+// In dry-run mode, the API server validates the write without persisting it
+#[instrument(skip_all, fields(
+    kind = %K::kind(&()),
+    name = %desired.name_any(),
+    dry_run = ctx.dry_run,
+    result = field::Empty,
+))]
+async fn write_target<K>(
+    ctx: &Context,
+    owner: &ObjectReference,
+    namespace: &str,
+    live: Option<&K>,
+    mut desired: K,
+    data: fn(&K) -> TargetData<'_>,
+) -> Result<Option<String>>
+where
+    K: Cached + Serialize,
+{
+    let hash = content_hash(&data(&desired), &desired.meta().annotations);
+    let live_hash = live.map(|live| content_hash(&data(live), &live.meta().annotations));
+    if live_hash.as_ref() == Some(&hash) {
+        info!("{} {} is up to date", K::kind(&()), desired.name_any());
+        Span::current().record("result", "unchanged");
+        return Ok(None);
+    }
+    let change = format!(
+        "{} {}: {}",
+        K::kind(&()),
+        desired.name_any(),
+        data_diff(live.map(data).as_ref(), &data(&desired))
+    );
+    set_content_hash(desired.meta_mut(), hash);
+    let api: Api<K> = Api::namespaced(ctx.client.clone(), namespace);
+    let params = PostParams {
+        dry_run: ctx.dry_run,
+        ..Default::default()
+    };
+    let result = match live {
+        Some(_) => api.replace(&desired.name_any(), &params, &desired).await,
+        None => api.create(&params, &desired).await,
+    };
+    match result {
+        Ok(_) if ctx.dry_run => {
+            info!("dry run, {}", change);
+            Span::current().record("result", "dry-run");
+        }
+        Ok(object) => {
+            info!("{} {} is updated", K::kind(&()), object.name_any());
+            let reason = if live.is_some() {
+                TARGET_UPDATED
+            } else {
+                TARGET_CREATED
+            };
+            Span::current().record("result", reason);
+            ctx.events
+                .target_changed(owner, &object.object_ref(&()), reason)
+                .await;
+        }
+        Err(err) => {
+            error!("{}", err);
+            let result = match &err {
+                // The live object is outdated, it's read again when the reconcile is retried
+                kube::Error::Api(response) if response.code == 409 => {
+                    ctx.cache.forget::<K>(namespace, &desired.name_any());
+                    "Conflict"
+                }
+                _ => "Failed",
+            };
+            Span::current().record("result", result);
+            return Err(Error::KubeError(err));
+        }
+    };
+    Ok(Some(change))
+}
+
 impl ConfigSet {
+    // Write the result of the reconciliation to the status
+    async fn update_status(&self, ctx: Arc<Context>, result: &Result<Vec<String>>) -> Result<()> {
+        let previous = self.status.clone().unwrap_or_default();
+        let paused = is_paused(self);
+        let conditions = vec![paused_condition(
+            paused,
+            &previous.conditions,
+            self.metadata.generation,
+        )];
+        let reconcile_at = self.annotations().get(RECONCILE_AT_ANNOTATION).cloned();
+        let status = match result {
+            Ok(_) if paused => ConfigSetStatus {
+                ready: previous.ready,
+                reason: Some(PAUSED_CONDITION.to_string()),
+                message: None,
+                targets: self.spec.targets.len() as i64,
+                last_sync_time: previous.last_sync_time,
+                observed_generation: previous.observed_generation,
+                last_handled_reconcile_at: previous.last_handled_reconcile_at,
+                pending_changes: previous.pending_changes,
+                conditions,
+            },
+            Ok(changes) => ConfigSetStatus {
+                ready: true,
+                reason: Some(if ctx.dry_run { "DryRun" } else { "Synced" }.to_string()),
+                message: None,
+                targets: self.spec.targets.len() as i64,
+                last_sync_time: Some(Utc::now()),
+                observed_generation: self.metadata.generation,
+                last_handled_reconcile_at: reconcile_at,
+                pending_changes: if ctx.dry_run { changes.clone() } else { vec![] },
+                conditions,
+            },
+            Err(err) => ConfigSetStatus {
+                ready: false,
+                reason: Some(err.reason()),
+                message: Some(logging::redact(&err.to_string())),
+                targets: self.spec.targets.len() as i64,
+                last_sync_time: previous.last_sync_time,
+                observed_generation: self.metadata.generation,
+                last_handled_reconcile_at: reconcile_at,
+                pending_changes: previous.pending_changes,
+                conditions,
+            },
+        };
+        let patch = json!({
+            "apiVersion": ConfigSet::api_version(&()),
+            "kind": ConfigSet::kind(&()),
+            "status": status,
+        });
+        let confsets: Api<ConfigSet> =
+            Api::namespaced(ctx.client.clone(), &object_namespace(self)?);
+        match confsets
+            .patch_status(
+                self.name_any().as_str(),
+                &PatchParams::apply(SHU_FIELD_MANAGER).force(),
+                &Patch::Apply(&patch),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("{}", err);
+                Err(Error::KubeError(err))
+            }
+        }
+    }
+
     // Reconcile (for non-finalizer related changes)
-    async fn reconcile(&self, ctx: Arc<Context>) -> Result<Action> {
+    async fn reconcile(&self, ctx: Arc<Context>) -> Result<Vec<String>> {
         /*
          * First we need to get inputs and write them to the map
          * Then use them to build new values with templates
          * And then write those values to targets
          */
+        if let Err(err) = self.spec.validate() {
+            return Err(Error::IllegalConfigSet(Box::from(err)));
+        }
+        let namespace = object_namespace(self)?;
 
-        let inputs: HashMap<String, String> = gather_inputs(
-            ctx.client.clone(),
-            self.metadata.namespace.clone().unwrap(),
-            self.spec.inputs.clone(),
-        )
-        .await?;
+        let inputs: HashMap<String, String> =
+            match gather_inputs(&ctx, namespace.clone(), self.spec.inputs.clone()).await {
+                Ok(inputs) => inputs,
+                Err(err) => {
+                    ctx.metrics.input_failure(CONTROLLER_KIND);
+                    ctx.events
+                        .warning(
+                            &self.object_ref(&()),
+                            INPUT_MISSING,
+                            ACTION_RECONCILE,
+                            err.to_string(),
+                        )
+                        .await;
+                    return Err(err);
+                }
+            };
 
-        let owner_reference = build_owner_refenerce(self.clone());
+        let owner_reference = build_owner_refenerce(self)?;
 
-        let (mut target_secrets, mut target_configmaps) = gather_targets(
-            ctx.client.clone(),
-            self.metadata.namespace.clone().unwrap(),
+        let (live_secrets, live_configmaps) =
+            gather_existing_targets(&ctx, namespace.clone(), self.spec.targets.clone()).await?;
+        let (mut target_secrets, mut target_configmaps) = prepare_targets(
+            namespace.clone(),
             self.spec.targets.clone(),
             owner_reference,
-        )
-        .await?;
+            &live_secrets,
+            &live_configmaps,
+        );
 
-        build_templates(
+        if let Err(err) = build_templates(
             self.spec.templates.clone(),
             &mut target_secrets,
             &mut target_configmaps,
             self.spec.targets.clone(),
             inputs.clone(),
-            self.metadata.name.clone().unwrap(),
-        )?;
-
-        for (_, value) in target_secrets {
-            let secrets =
-                get_secret_api(ctx.client.clone(), self.metadata.namespace.clone().unwrap());
-            match secrets
-                .replace(
-                    value.metadata.name.clone().unwrap().as_str(),
-                    &PostParams::default(),
-                    &value,
+            self.name_any(),
+        ) {
+            ctx.metrics.template_failure(CONTROLLER_KIND);
+            ctx.events
+                .warning(
+                    &self.object_ref(&()),
+                    TEMPLATE_ERROR,
+                    ACTION_RECONCILE,
+                    err.to_string(),
                 )
-                .await
-            {
-                Ok(sec) => {
-                    info!("secret {} is updated", sec.metadata.name.unwrap());
-                }
-                Err(err) => {
-                    error!("{}", err);
-                    return Err(Error::KubeError(err));
-                }
-            };
+                .await;
+            return Err(err);
         }
-        for (_, value) in target_configmaps {
-            let configmaps =
-                get_configmap_api(ctx.client.clone(), self.metadata.namespace.clone().unwrap());
-            match configmaps
-                .replace(
-                    value.metadata.name.clone().unwrap().as_str(),
-                    &PostParams::default(),
-                    &value,
-                )
-                .await
-            {
-                Ok(sec) => {
-                    info!("secret {} is updated", sec.metadata.name.unwrap());
-                }
-                Err(err) => {
-                    error!("{}", err);
-                    return Err(Error::KubeError(err));
-                }
-            };
-        }
-        Ok::<Action, Error>(Action::await_change())
-    }
 
-    // Finalizer cleanup (the object was deleted, ensure nothing is orphaned)
-    async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action> {
-        let inputs: HashMap<String, String> = gather_inputs(
-            ctx.client.clone(),
-            self.metadata.namespace.clone().unwrap(),
-            self.spec.inputs.clone(),
+        write_targets(
+            &ctx,
+            &self.object_ref(&()),
+            namespace,
+            target_secrets,
+            target_configmaps,
+            &live_secrets,
+            &live_configmaps,
         )
-        .await?;
-
-        let owner_reference = build_owner_refenerce(self.clone());
+        .await
+    }
 
-        let (mut target_secrets, mut target_configmaps) = gather_targets(
-            ctx.client.clone(),
-            self.metadata.namespace.clone().unwrap(),
-            self.spec.targets.clone(),
-            owner_reference,
-        )
-        .await?;
+    // Finalizer cleanup (the object was deleted, ensure nothing is orphaned).
+    // Inputs are not needed, so missing inputs don't block the deletion.
+    // Paused objects are deleted without touching targets, rendered keys are kept
+    async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action> {
+        if is_paused(self) {
+            info!(
+                "{} is paused, targets are left as they are",
+                self.name_any()
+            );
+            return Ok(Action::await_change());
+        }
+        let namespace = object_namespace(self)?;
+        let (live_secrets, live_configmaps) =
+            gather_existing_targets(&ctx, namespace.clone(), self.spec.targets.clone()).await?;
+        let (mut target_secrets, mut target_configmaps) =
+            (live_secrets.clone(), live_configmaps.clone());
         cleanup_templates(
             self.spec.templates.clone(),
             &mut target_secrets,
@@ -491,46 +1064,307 @@ impl ConfigSet {
             self.spec.targets.clone(),
         )?;
 
-        for (_, value) in target_secrets {
-            let secrets =
-                get_secret_api(ctx.client.clone(), self.metadata.namespace.clone().unwrap());
-            match secrets
-                .replace(
-                    value.metadata.name.clone().unwrap().as_str(),
-                    &PostParams::default(),
-                    &value,
-                )
-                .await
-            {
-                Ok(sec) => {
-                    info!("secret {} is updated", sec.metadata.name.unwrap());
-                }
-                Err(err) => {
-                    error!("{}", err);
-                    return Err(Error::KubeError(err));
-                }
-            };
+        write_targets(
+            &ctx,
+            &self.object_ref(&()),
+            namespace,
+            target_secrets,
+            target_configmaps,
+            &live_secrets,
+            &live_configmaps,
+        )
+        .await?;
+        Ok::<Action, Error>(Action::await_change())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::v1alpha1::configsets_api::{ConfigSetSpec, Target};
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    fn input(kind: Kinds) -> Input {
+        Input {
+            kind,
+            name: "database".to_string(),
+            key: "PASSWORD".to_string(),
         }
-        for (_, value) in target_configmaps {
-            let configmaps =
-                get_configmap_api(ctx.client.clone(), self.metadata.namespace.clone().unwrap());
-            match configmaps
-                .replace(
-                    value.metadata.name.clone().unwrap().as_str(),
-                    &PostParams::default(),
-                    &value,
-                )
-                .await
-            {
-                Ok(sec) => {
-                    info!("secret {} is updated", sec.metadata.name.unwrap());
+    }
+
+    fn binary_configmap(value: &[u8]) -> ConfigMap {
+        ConfigMap {
+            binary_data: Some(BTreeMap::from([(
+                "PASSWORD".to_string(),
+                ByteString(value.to_vec()),
+            )])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn secrets_without_data_miss_keys() {
+        let secret = Secret {
+            data: None,
+            ..Default::default()
+        };
+        let err = secret_input_value(&secret, &input(Kinds::Secret)).unwrap_err();
+        assert!(matches!(err, Error::KeyNotFound { .. }));
+    }
+
+    #[test]
+    fn binary_data_of_configmaps_is_read() {
+        let configmap = binary_configmap(b"qwerty");
+        let value = configmap_input_value(&configmap, &input(Kinds::ConfigMap)).unwrap();
+        assert_eq!(value, "qwerty");
+    }
+
+    #[test]
+    fn binary_data_must_be_utf8() {
+        let configmap = binary_configmap(&[0xff, 0xfe]);
+        let err = configmap_input_value(&configmap, &input(Kinds::ConfigMap)).unwrap_err();
+        assert!(matches!(err, Error::InvalidUtf8 { .. }));
+    }
+
+    #[test]
+    fn objects_without_namespace_are_rejected() {
+        let confset = ConfigSet::new("test", Default::default());
+        let err = object_namespace(&confset).unwrap_err();
+        assert!(matches!(err, Error::MissingMetadata(field) if field == "namespace"));
+    }
+
+    #[derive(Default)]
+    struct Requests {
+        gets: AtomicUsize,
+        writes: AtomicUsize,
+        // Writes of ConfigMaps fail, and the API server quotes their data in the error
+        reject_configmaps: AtomicBool,
+    }
+
+    fn status_response(code: u16, reason: &str, message: &str) -> http::Response<hyper::Body> {
+        let status = json!({
+            "apiVersion": "v1",
+            "kind": "Status",
+            "status": "Failure",
+            "reason": reason,
+            "message": message,
+            "code": code,
+        });
+        http::Response::builder()
+            .status(code)
+            .body(hyper::Body::from(status.to_string()))
+            .unwrap()
+    }
+
+    // Reads are answered with 404, writes are answered with the written object,
+    // and writes of the status with the ConfigSet
+    fn mock_client(requests: Arc<Requests>) -> Client {
+        let service = tower::service_fn(move |request: http::Request<hyper::Body>| {
+            let requests = requests.clone();
+            async move {
+                if request.method() == http::Method::GET {
+                    requests.gets.fetch_add(1, Ordering::SeqCst);
+                    return Ok::<_, Infallible>(status_response(404, "NotFound", "not found"));
                 }
-                Err(err) => {
-                    error!("{}", err);
-                    return Err(Error::KubeError(err));
+                requests.writes.fetch_add(1, Ordering::SeqCst);
+                let path = request.uri().path().to_string();
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                if path.ends_with("/status") {
+                    let confset = serde_json::to_string(&configset()).unwrap();
+                    return Ok(http::Response::new(hyper::Body::from(confset)));
                 }
-            };
+                if path.contains("/configmaps") && requests.reject_configmaps.load(Ordering::SeqCst)
+                {
+                    let message = format!("ConfigMap is invalid: {}", from_utf8(&body).unwrap());
+                    return Ok(status_response(422, "Invalid", &message));
+                }
+                Ok(http::Response::new(hyper::Body::from(body)))
+            }
+        });
+        Client::new(service, "default")
+    }
+
+    fn mock_context(requests: Arc<Requests>, secrets: Vec<Secret>) -> Arc<Context> {
+        let client = mock_client(requests);
+        let metrics = Arc::new(Metrics::new());
+        Arc::new(Context {
+            client: client.clone(),
+            metrics: metrics.clone(),
+            events: Events::new(client.clone()),
+            backoff: Arc::new(Backoff::new(
+                Duration::from_secs(1),
+                Duration::from_secs(10),
+            )),
+            cache: Cache::with_objects(client, secrets, vec![], metrics),
+            resync_interval: None,
+            dry_run: false,
+            namespaces: None,
+        })
+    }
+
+    fn secret(name: &str, data: &[(&str, &str)]) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some("default".to_string()),
+                ..Default::default()
+            },
+            data: Some(
+                data.iter()
+                    .map(|(key, value)| (key.to_string(), ByteString(value.as_bytes().to_vec())))
+                    .collect(),
+            ),
+            ..Default::default()
         }
-        Ok::<Action, Error>(Action::await_change())
+    }
+
+    fn configset() -> ConfigSet {
+        let mut confset = ConfigSet::new(
+            "app",
+            ConfigSetSpec {
+                inputs: vec![InputWithName {
+                    name: "PASSWORD".to_string(),
+                    from: Input {
+                        kind: Kinds::Secret,
+                        name: "database".to_string(),
+                        key: "PASSWORD".to_string(),
+                    },
+                }],
+                targets: vec![TargetWithName {
+                    name: "app".to_string(),
+                    target: Target {
+                        kind: Kinds::Secret,
+                        name: "app".to_string(),
+                    },
+                }],
+                templates: vec![Templates {
+                    name: "CONNECTION".to_string(),
+                    template: "postgres://app:{{PASSWORD}}@db".to_string(),
+                    target: "app".to_string(),
+                }],
+                ..Default::default()
+            },
+        );
+        confset.metadata.namespace = Some("default".to_string());
+        confset.metadata.uid = Some("0c5f1c6e".to_string());
+        confset
+    }
+
+    #[tokio::test]
+    async fn warm_cache_is_not_bypassed() {
+        let requests = Arc::new(Requests::default());
+        let ctx = mock_context(
+            requests.clone(),
+            vec![
+                secret("database", &[("PASSWORD", "qwerty")]),
+                secret("app", &[]),
+            ],
+        );
+        let confset = configset();
+        for _ in 0..2 {
+            confset.reconcile(ctx.clone()).await.unwrap();
+        }
+        assert_eq!(requests.gets.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn objects_missing_in_synced_stores_are_not_read() {
+        let requests = Arc::new(Requests::default());
+        let ctx = mock_context(requests.clone(), vec![secret("app", &[])]);
+        let err = configset().reconcile(ctx).await.unwrap_err();
+        assert!(matches!(err, Error::InputNotFound { .. }));
+        assert_eq!(requests.gets.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn missing_targets_are_created_without_reads() {
+        let requests = Arc::new(Requests::default());
+        let ctx = mock_context(
+            requests.clone(),
+            vec![secret("database", &[("PASSWORD", "qwerty")])],
+        );
+        let changes = configset().reconcile(ctx).await.unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(requests.gets.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn paused_configsets_leave_targets_on_cleanup() {
+        let requests = Arc::new(Requests::default());
+        let ctx = mock_context(
+            requests.clone(),
+            vec![secret("app", &[("CONNECTION", "postgres://app:qwerty@db")])],
+        );
+        let mut confset = configset();
+        confset
+            .annotations_mut()
+            .insert(PAUSED_ANNOTATION.to_string(), "true".to_string());
+        confset.cleanup(ctx).await.unwrap();
+        assert_eq!(requests.writes.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn values_are_not_logged() {
+        logging::capture::install();
+        let input = "s3cr3t-4f1d";
+        let rendered = format!("postgres://app:{}@db", input);
+        let requests = Arc::new(Requests::default());
+        let ctx = mock_context(
+            requests.clone(),
+            vec![secret("database", &[("PASSWORD", input)])],
+        );
+        let mut confset = configset();
+        confset.finalizers_mut().push(SHU_FINALIZER.to_string());
+        confset.spec.targets[0].target.kind = Kinds::ConfigMap;
+
+        // The API server rejects the rendered value and quotes it
+        requests.reject_configmaps.store(true, Ordering::SeqCst);
+        let err = reconcile(Arc::new(confset.clone()), ctx.clone())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("[REDACTED]"), "{}", err);
+        let mut errors = vec![err.to_string()];
+
+        // The helper error quotes the input value
+        confset.spec.templates[0].template = "{{#if (lt PASSWORD 1)}}{{/if}}".to_string();
+        let err = reconcile(Arc::new(confset), ctx).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::FinalizerError(err) if matches!(err.as_ref(), finalizer::Error::ApplyFailed(Error::TemplateRender { .. })))
+        );
+        errors.push(err.to_string());
+
+        let lines = logging::capture::lines();
+        for line in lines.iter().chain(errors.iter()) {
+            assert!(!line.contains(input), "{}", line);
+            assert!(!line.contains(&rendered), "{}", line);
+        }
+        assert!(lines.iter().any(|line| line.contains("[REDACTED]")));
+    }
+
+    #[test]
+    fn templates_need_gathered_targets() {
+        let targets = vec![TargetWithName {
+            name: "app".to_string(),
+            target: Target {
+                kind: Kinds::Secret,
+                name: "app".to_string(),
+            },
+        }];
+        let templates = vec![Templates {
+            name: "PASSWORD".to_string(),
+            template: "qwerty".to_string(),
+            target: "app".to_string(),
+        }];
+        let err = build_templates(
+            templates,
+            &mut HashMap::new(),
+            &mut HashMap::new(),
+            targets,
+            HashMap::new(),
+            "test".to_string(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::TargetMissing(target) if target == "app"));
     }
 }
@@ -0,0 +1,120 @@
+use crate::{logging, telemetry};
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use kube::Client;
+use log::*;
+
+// Reasons of events, they are shown by `kubectl describe`
+pub(crate) static TARGET_CREATED: &str = "TargetCreated";
+pub(crate) static TARGET_UPDATED: &str = "TargetUpdated";
+pub(crate) static INPUT_MISSING: &str = "InputMissing";
+pub(crate) static TEMPLATE_ERROR: &str = "TemplateError";
+pub(crate) static CLEANUP_FAILED: &str = "CleanupFailed";
+
+// Actions that were taken when events were published
+pub(crate) static ACTION_RECONCILE: &str = "Reconcile";
+pub(crate) static ACTION_CLEANUP: &str = "Cleanup";
+
+/// Publishes Kubernetes Events for ConfigSets and their targets.
+/// Events are best effort, so failures are only logged
+#[derive(Clone)]
+pub(crate) struct Events {
+    client: Client,
+    reporter: Reporter,
+}
+
+impl Events {
+    pub(crate) fn new(client: Client) -> Self {
+        Events {
+            client,
+            reporter: Reporter {
+                controller: "shoebill".to_string(),
+                instance: std::env::var("HOSTNAME").ok(),
+            },
+        }
+    }
+
+    pub(crate) async fn normal(
+        &self,
+        object: &ObjectReference,
+        reason: &str,
+        action: &str,
+        note: String,
+    ) {
+        self.publish(object, EventType::Normal, reason, action, note)
+            .await
+    }
+
+    pub(crate) async fn warning(
+        &self,
+        object: &ObjectReference,
+        reason: &str,
+        action: &str,
+        note: String,
+    ) {
+        self.publish(object, EventType::Warning, reason, action, note)
+            .await
+    }
+
+    /// Publish the event on both the ConfigSet and the target it has changed
+    pub(crate) async fn target_changed(
+        &self,
+        owner: &ObjectReference,
+        target: &ObjectReference,
+        reason: &str,
+    ) {
+        let target_kind = target.kind.clone().unwrap_or_default();
+        let target_name = target.name.clone().unwrap_or_default();
+        let verb = if reason == TARGET_CREATED {
+            "created"
+        } else {
+            "updated"
+        };
+        self.normal(
+            owner,
+            reason,
+            ACTION_RECONCILE,
+            format!("{} {} is {}", target_kind, target_name, verb),
+        )
+        .await;
+        self.normal(
+            target,
+            reason,
+            ACTION_RECONCILE,
+            format!(
+                "{} by {} {}",
+                verb,
+                owner.kind.clone().unwrap_or_default(),
+                owner.name.clone().unwrap_or_default()
+            ),
+        )
+        .await;
+    }
+
+    async fn publish(
+        &self,
+        object: &ObjectReference,
+        type_: EventType,
+        reason: &str,
+        action: &str,
+        note: String,
+    ) {
+        let recorder = Recorder::new(self.client.clone(), self.reporter.clone(), object.clone());
+        // Error messages may contain values of inputs and templates
+        let note = logging::redact(&note);
+        let note = match telemetry::trace_id() {
+            Some(trace_id) => format!("{} (trace id {})", note, trace_id),
+            None => note,
+        };
+        let event = Event {
+            type_,
+            reason: reason.to_string(),
+            note: Some(note),
+            action: action.to_string(),
+            secondary: None,
+        };
+        if let Err(err) = recorder.publish(event).await {
+            warn!("failed to publish the event {}: {}", reason, err);
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use crate::{Error, Result};
+use chrono::Utc;
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+use kube::api::PostParams;
+use kube::core::ObjectMeta;
+use kube::{Api, Client};
+use log::*;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Lease-based leader election, so several replicas can be running,
+/// but only one of them is reconciling ConfigSets at a time.
+/// The same algorithm as in client-go is used: the lease is held
+/// while it's renewed, and it can be taken over once it's expired
+pub(crate) struct LeaderElection {
+    api: Api<Lease>,
+    name: String,
+    identity: String,
+    lease_duration: Duration,
+    renew_deadline: Duration,
+    // The last seen spec of the lease and when it was seen. Expiration is
+    // counted from the local time the lease changed, not from its renewTime,
+    // so clocks of replicas don't have to be in sync
+    observed: Mutex<Option<(LeaseSpec, Instant)>>,
+}
+
+impl LeaderElection {
+    pub(crate) fn new(
+        client: Client,
+        namespace: &str,
+        name: String,
+        identity: String,
+        lease_duration: Duration,
+        renew_deadline: Duration,
+    ) -> Self {
+        LeaderElection {
+            api: Api::namespaced(client, namespace),
+            name,
+            identity,
+            lease_duration,
+            renew_deadline,
+            observed: Mutex::new(None),
+        }
+    }
+
+    // Leases are renewed a few times during the renew deadline,
+    // so a single failed request doesn't lose the leadership
+    fn retry_period(&self) -> Duration {
+        self.renew_deadline / 4
+    }
+
+    // Returns when the spec was seen for the first time
+    fn observe(&self, spec: &LeaseSpec) -> Instant {
+        let mut observed = self.observed.lock().unwrap();
+        match observed.as_ref() {
+            Some((last, seen_at)) if last == spec => *seen_at,
+            _ => {
+                let seen_at = Instant::now();
+                *observed = Some((spec.clone(), seen_at));
+                seen_at
+            }
+        }
+    }
+
+    /// Block until this replica becomes the leader
+    pub(crate) async fn acquire(&self) {
+        info!("waiting for the lease {} as {}", self.name, self.identity);
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => {
+                    info!("acquired the lease {}", self.name);
+                    return;
+                }
+                Ok(false) => debug!("lease {} is held by another replica", self.name),
+                Err(err) => error!("failed to acquire the lease {}: {}", self.name, err),
+            }
+            tokio::time::sleep(self.retry_period()).await;
+        }
+    }
+
+    /// Keep renewing the lease, returns when the leadership is lost.
+    /// After that the replica must stop reconciling. It happens once the lease
+    /// isn't renewed within the renew deadline, that is shorter than the lease
+    /// duration, so the replica stops before another one can take over
+    pub(crate) async fn renew(&self) {
+        let mut last_renew = Instant::now();
+        loop {
+            tokio::time::sleep(self.retry_period()).await;
+            let remaining = self.renew_deadline.saturating_sub(last_renew.elapsed());
+            match tokio::time::timeout(remaining, self.try_acquire_or_renew()).await {
+                Ok(Ok(true)) => last_renew = Instant::now(),
+                Ok(Ok(false)) => {
+                    error!("the lease {} was taken by another replica", self.name);
+                    return;
+                }
+                Ok(Err(err)) => error!("failed to renew the lease {}: {}", self.name, err),
+                Err(_) => error!("renewal of the lease {} timed out", self.name),
+            }
+            if last_renew.elapsed() >= self.renew_deadline {
+                error!(
+                    "the lease {} wasn't renewed within the renew deadline",
+                    self.name
+                );
+                return;
+            }
+        }
+    }
+
+    /// Run controllers while the lease is held, the lease is released once they
+    /// are stopped. Fails when the leadership is lost, controllers are dropped then
+    pub(crate) async fn lead<F: Future>(&self, controllers: F) -> Result<()> {
+        tokio::select! {
+            _ = controllers => {
+                self.release().await;
+                Ok(())
+            }
+            _ = self.renew() => Err(Error::LeadershipLost(self.name.clone())),
+        }
+    }
+
+    /// Give the lease up, so another replica doesn't have to wait until it's expired
+    pub(crate) async fn release(&self) {
+        let lease = match self.api.get_opt(&self.name).await {
+            Ok(Some(lease)) => lease,
+            Ok(None) => return,
+            Err(err) => {
+                error!("failed to release the lease {}: {}", self.name, err);
+                return;
+            }
+        };
+        let mut spec = lease.spec.clone().unwrap_or_default();
+        if spec.holder_identity.as_deref() != Some(self.identity.as_str()) {
+            return;
+        }
+        spec.holder_identity = None;
+        spec.acquire_time = None;
+        spec.renew_time = None;
+        let released = Lease {
+            metadata: lease.metadata.clone(),
+            spec: Some(spec),
+        };
+        match self
+            .api
+            .replace(&self.name, &PostParams::default(), &released)
+            .await
+        {
+            Ok(_) => info!("released the lease {}", self.name),
+            Err(err) => error!("failed to release the lease {}: {}", self.name, err),
+        }
+    }
+
+    // Returns true when this replica holds the lease after the call.
+    // Conflicts mean that another replica has updated the lease first
+    async fn try_acquire_or_renew(&self) -> std::result::Result<bool, kube::Error> {
+        let now = MicroTime(Utc::now());
+        let lease_duration_seconds = self.lease_duration.as_secs() as i32;
+        let Some(lease) = self.api.get_opt(&self.name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.name.clone()),
+                    ..Default::default()
+                },
+                spec: Some(LeaseSpec {
+                    holder_identity: Some(self.identity.clone()),
+                    lease_duration_seconds: Some(lease_duration_seconds),
+                    acquire_time: Some(now.clone()),
+                    renew_time: Some(now),
+                    lease_transitions: Some(0),
+                }),
+            };
+            return match self.api.create(&PostParams::default(), &lease).await {
+                Ok(_) => Ok(true),
+                Err(kube::Error::Api(err)) if err.code == 409 => Ok(false),
+                Err(err) => Err(err),
+            };
+        };
+
+        let mut spec = lease.spec.clone().unwrap_or_default();
+        let observed_at = self.observe(&spec);
+        let held_by_us = spec.holder_identity.as_deref() == Some(self.identity.as_str());
+        if !held_by_us && !lease_is_free(&spec, observed_at.elapsed()) {
+            return Ok(false);
+        }
+        if !held_by_us {
+            spec.holder_identity = Some(self.identity.clone());
+            spec.acquire_time = Some(now.clone());
+            spec.lease_transitions = Some(spec.lease_transitions.unwrap_or_default() + 1);
+        }
+        spec.lease_duration_seconds = Some(lease_duration_seconds);
+        spec.renew_time = Some(now);
+        // The resource version is kept, so only one replica can win the update
+        let updated = Lease {
+            metadata: lease.metadata.clone(),
+            spec: Some(spec.clone()),
+        };
+        match self
+            .api
+            .replace(&self.name, &PostParams::default(), &updated)
+            .await
+        {
+            Ok(_) => {
+                self.observe(&spec);
+                Ok(true)
+            }
+            Err(kube::Error::Api(err)) if err.code == 409 => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// The lease can be taken when nobody holds it or when it wasn't renewed
+/// for the lease duration since it was seen changed the last time
+fn lease_is_free(spec: &LeaseSpec, unchanged_for: Duration) -> bool {
+    if spec
+        .holder_identity
+        .as_deref()
+        .unwrap_or_default()
+        .is_empty()
+    {
+        return true;
+    }
+    match spec.lease_duration_seconds {
+        Some(duration) => unchanged_for > Duration::from_secs(duration.max(0) as u64),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// The Lease as it's stored by the API server, writes with an outdated
+    /// resource version are rejected, and every request fails while `down` is set
+    #[derive(Default)]
+    struct LeaseServer {
+        lease: Mutex<Option<Lease>>,
+        down: AtomicBool,
+    }
+
+    impl LeaseServer {
+        fn holder(&self) -> Option<String> {
+            let lease = self.lease.lock().unwrap();
+            lease
+                .as_ref()
+                .and_then(|lease| lease.spec.clone()?.holder_identity)
+        }
+
+        // Another replica writes the lease
+        fn update(&self, spec: LeaseSpec) {
+            let mut lease = self.lease.lock().unwrap();
+            let version = next_version(lease.as_ref());
+            *lease = Some(Lease {
+                metadata: ObjectMeta {
+                    name: Some("shoebill-controller".to_string()),
+                    resource_version: Some(version),
+                    ..Default::default()
+                },
+                spec: Some(spec),
+            });
+        }
+    }
+
+    fn next_version(lease: Option<&Lease>) -> String {
+        let version: u64 = lease
+            .and_then(|lease| lease.metadata.resource_version.clone())
+            .map(|version| version.parse().unwrap())
+            .unwrap_or_default();
+        (version + 1).to_string()
+    }
+
+    fn status_response(code: u16, reason: &str) -> http::Response<hyper::Body> {
+        let status = json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "status": "Failure",
+            "message": reason,
+            "reason": reason,
+            "code": code,
+        });
+        http::Response::builder()
+            .status(code)
+            .body(hyper::Body::from(status.to_string()))
+            .unwrap()
+    }
+
+    fn mock_client(server: Arc<LeaseServer>) -> Client {
+        let service = tower::service_fn(move |request: http::Request<hyper::Body>| {
+            let server = server.clone();
+            async move {
+                if server.down.load(Ordering::SeqCst) {
+                    return Ok::<_, Infallible>(status_response(500, "InternalError"));
+                }
+                let method = request.method().clone();
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                let mut stored = server.lease.lock().unwrap();
+                let response = match method {
+                    http::Method::GET => match stored.as_ref() {
+                        Some(lease) => serde_json::to_string(lease).unwrap(),
+                        None => return Ok(status_response(404, "NotFound")),
+                    },
+                    _ => {
+                        let mut lease: Lease = serde_json::from_slice(&body).unwrap();
+                        let current = stored.as_ref().map(|lease| {
+                            lease.metadata.resource_version.clone().unwrap_or_default()
+                        });
+                        let expected = match method {
+                            http::Method::POST => None,
+                            _ => lease.metadata.resource_version.clone(),
+                        };
+                        if current != expected {
+                            return Ok(status_response(409, "Conflict"));
+                        }
+                        lease.metadata.resource_version = Some(next_version(stored.as_ref()));
+                        let response = serde_json::to_string(&lease).unwrap();
+                        *stored = Some(lease);
+                        response
+                    }
+                };
+                Ok(http::Response::new(hyper::Body::from(response)))
+            }
+        });
+        Client::new(service, "default")
+    }
+
+    fn election(server: Arc<LeaseServer>) -> LeaderElection {
+        LeaderElection::new(
+            mock_client(server),
+            "default",
+            "shoebill-controller".to_string(),
+            "replica-a".to_string(),
+            Duration::from_secs(15),
+            Duration::from_millis(200),
+        )
+    }
+
+    fn held_lease(renewed_seconds_ago: i64) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: Some("leader".to_string()),
+            lease_duration_seconds: Some(15),
+            renew_time: Some(MicroTime(
+                Utc::now() - chrono::Duration::seconds(renewed_seconds_ago),
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn released_leases_are_free() {
+        let spec = LeaseSpec {
+            holder_identity: None,
+            ..held_lease(0)
+        };
+        assert!(lease_is_free(&spec, Duration::ZERO));
+    }
+
+    #[test]
+    fn expiry_is_counted_from_local_observation() {
+        // The renew time of the leader is ignored, it may be skewed either way
+        assert!(!lease_is_free(&held_lease(3600), Duration::from_secs(5)));
+        assert!(lease_is_free(&held_lease(-3600), Duration::from_secs(16)));
+    }
+
+    #[tokio::test]
+    async fn expired_leases_are_taken_over() {
+        let server = Arc::new(LeaseServer::default());
+        server.update(LeaseSpec {
+            lease_transitions: Some(3),
+            ..held_lease(0)
+        });
+        let election = election(server.clone());
+        assert!(!election.try_acquire_or_renew().await.unwrap());
+        assert_eq!(server.holder().as_deref(), Some("leader"));
+
+        // The leader hasn't renewed the lease for longer than its duration
+        election.observed.lock().unwrap().as_mut().unwrap().1 -= Duration::from_secs(16);
+        assert!(election.try_acquire_or_renew().await.unwrap());
+        let spec = server.lease.lock().unwrap().clone().unwrap().spec.unwrap();
+        assert_eq!(spec.holder_identity.as_deref(), Some("replica-a"));
+        assert_eq!(spec.lease_transitions, Some(4));
+        assert!(spec.acquire_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn taken_leases_stop_the_leader() {
+        let server = Arc::new(LeaseServer::default());
+        let election = election(server.clone());
+        election.acquire().await;
+        assert_eq!(server.holder().as_deref(), Some("replica-a"));
+
+        server.update(held_lease(0));
+        let result = election.lead(futures::future::pending::<()>()).await;
+        assert!(
+            matches!(result, Err(Error::LeadershipLost(name)) if name == "shoebill-controller")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_renewals_stop_the_leader_within_the_deadline() {
+        let server = Arc::new(LeaseServer::default());
+        let election = election(server.clone());
+        election.acquire().await;
+
+        server.down.store(true, Ordering::SeqCst);
+        let started = Instant::now();
+        let result = election.lead(futures::future::pending::<()>()).await;
+        assert!(matches!(result, Err(Error::LeadershipLost(_))));
+        // Standbys can only take over after the lease duration
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn stopped_leaders_release_the_lease() {
+        let server = Arc::new(LeaseServer::default());
+        let election = election(server.clone());
+        election.acquire().await;
+
+        election.lead(async {}).await.unwrap();
+        let spec = server.lease.lock().unwrap().clone().unwrap().spec.unwrap();
+        assert_eq!(spec.holder_identity, None);
+        assert_eq!(spec.renew_time, None);
+        assert!(lease_is_free(&spec, Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn leases_of_other_replicas_are_not_released() {
+        let server = Arc::new(LeaseServer::default());
+        server.update(held_lease(0));
+        election(server.clone()).release().await;
+        assert_eq!(server.holder().as_deref(), Some("leader"));
+    }
+}
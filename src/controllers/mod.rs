@@ -1 +1,194 @@
-pub(crate) mod configsets_controller;
+use crate::controllers::backoff::Backoff;
+use crate::controllers::cache::Cache;
+use crate::controllers::leader_election::LeaderElection;
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::{Error, Result};
+use kube::Client;
+use log::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+pub mod backoff;
+pub mod cache;
+pub mod clusterconfigsets_controller;
+pub mod configsets_controller;
+pub mod events;
+pub mod leader_election;
+
+/// Lease that is used for leader election
+#[derive(Clone, Debug)]
+pub struct LeaseConfig {
+    /// Name of the Lease
+    pub name: String,
+    /// Namespace of the Lease, the namespace of the client is used if it's not set
+    pub namespace: Option<String>,
+    /// Identity of the replica, the hostname is used if it's not set
+    pub identity: Option<String>,
+    /// How long the leader holds the Lease without renewing it
+    pub duration: Duration,
+    /// How long the leader keeps reconciling without renewing the Lease,
+    /// it must be shorter than the duration
+    pub renew_deadline: Duration,
+}
+
+/// Configuration of the controllers started by [`run_controller`]
+#[derive(Clone)]
+pub struct ControllerConfig {
+    /// Only watch ConfigSets in these namespaces, all namespaces are watched
+    /// and ClusterConfigSets are reconciled when it's empty
+    pub namespaces: Vec<String>,
+    /// Only reconcile objects matching the label selector
+    pub selector: Option<String>,
+    /// Only cache Secrets and ConfigMaps matching the label selector,
+    /// other ones are read from the API when they are used
+    pub cache_selector: Option<String>,
+    /// Reconcile only when the Lease is held, if it's set
+    pub leader_election: Option<LeaseConfig>,
+    /// Delay before the first retry of a failed reconciliation
+    pub min_backoff: Duration,
+    /// Max delay between retries of a failed reconciliation
+    pub max_backoff: Duration,
+    /// How many objects can be reconciled at the same time by every controller, 0 is unlimited
+    pub max_concurrent_reconciles: u16,
+    /// Reconcile objects again after the interval, even if nothing has changed
+    pub resync_interval: Option<Duration>,
+    /// Run the reconciliation without changing targets, changes are
+    /// logged and recorded in the status of ConfigSets instead
+    pub dry_run: bool,
+    /// Metrics of controllers, they can be shared with the metrics endpoint
+    pub metrics: Arc<Metrics>,
+    /// State of controllers, it can be shared with health endpoints
+    pub health: Arc<Health>,
+}
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        ControllerConfig {
+            namespaces: vec![],
+            selector: None,
+            cache_selector: None,
+            leader_election: None,
+            min_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(5 * 60),
+            max_concurrent_reconciles: 0,
+            resync_interval: None,
+            dry_run: false,
+            metrics: Arc::new(Metrics::new()),
+            health: Arc::new(Health::new()),
+        }
+    }
+}
+
+/// Settings of reconciliation loops that are shared by all the controllers
+#[derive(Clone, Copy, Debug)]
+pub struct ReconcileSettings {
+    /// How many objects can be reconciled at the same time, 0 is unlimited
+    pub concurrency: u16,
+    /// Reconcile objects again after the interval, even if nothing has changed
+    pub resync_interval: Option<Duration>,
+    /// Only validate writes to targets by the API server, without persisting them
+    pub dry_run: bool,
+}
+
+impl ReconcileSettings {
+    pub(crate) fn controller_config(&self) -> kube::runtime::controller::Config {
+        kube::runtime::controller::Config::default().concurrency(self.concurrency)
+    }
+}
+
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = sigterm.recv() => {},
+    }
+}
+
+async fn setup_leader_election(lease: &LeaseConfig) -> Result<LeaderElection> {
+    // Otherwise the old leader may still be reconciling when a standby takes over
+    if lease.renew_deadline >= lease.duration {
+        return Err(Error::InvalidLeaseConfig(format!(
+            "the renew deadline {}s must be shorter than the lease duration {}s",
+            lease.renew_deadline.as_secs(),
+            lease.duration.as_secs()
+        )));
+    }
+    let client = Client::try_default().await.map_err(Error::KubeError)?;
+    let namespace = lease
+        .namespace
+        .clone()
+        .unwrap_or_else(|| client.default_namespace().to_string());
+    let identity = lease.identity.clone().unwrap_or_else(|| {
+        std::env::var("HOSTNAME").unwrap_or_else(|_| format!("shoebill-{}", std::process::id()))
+    });
+    Ok(LeaderElection::new(
+        client,
+        &namespace,
+        lease.name.clone(),
+        identity,
+        lease.duration,
+        lease.renew_deadline,
+    ))
+}
+
+/// Run the ConfigSet and ClusterConfigSet controllers until the process gets
+/// a shutdown signal. With leader election, controllers are only started
+/// on the leader, standby replicas are waiting for the Lease
+pub async fn run_controller(config: ControllerConfig) -> Result<()> {
+    let backoff = Arc::new(Backoff::new(config.min_backoff, config.max_backoff));
+    let client = Client::try_default().await.map_err(Error::KubeError)?;
+    let settings = ReconcileSettings {
+        concurrency: config.max_concurrent_reconciles,
+        resync_interval: config.resync_interval,
+        dry_run: config.dry_run,
+    };
+    let controllers = async {
+        // Caches are only started on the leader, together with controllers
+        let cache = Cache::start(
+            client,
+            &config.namespaces,
+            config.cache_selector.clone(),
+            config.metrics.clone(),
+            config.health.clone(),
+        );
+        let configsets = configsets_controller::setup(
+            config.metrics.clone(),
+            config.health.clone(),
+            backoff.clone(),
+            cache.clone(),
+            config.namespaces.clone(),
+            config.selector.clone(),
+            settings,
+        );
+        // ClusterConfigSets need access to the whole cluster
+        if config.namespaces.is_empty() {
+            let clusterconfigsets = clusterconfigsets_controller::setup(
+                config.metrics.clone(),
+                config.health.clone(),
+                backoff.clone(),
+                cache.clone(),
+                config.selector.clone(),
+                settings,
+            );
+            tokio::join!(configsets, clusterconfigsets);
+        } else {
+            info!(
+                "watching namespaces {}, clusterconfigsets are not reconciled",
+                config.namespaces.join(",")
+            );
+            configsets.await;
+        }
+    };
+    let Some(lease) = &config.leader_election else {
+        controllers.await;
+        return Ok(());
+    };
+    let election = setup_leader_election(lease).await?;
+    tokio::select! {
+        _ = election.acquire() => {},
+        _ = shutdown_signal() => return Ok(()),
+    }
+    election.lead(controllers).await
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the watcher may keep failing before the controller is considered stalled
+static STALLED_AFTER: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Default)]
+struct ControllerState {
+    client_connected: bool,
+    crds_installed: bool,
+    synced: bool,
+    stopped: bool,
+    // Set on the first failed watch, cleared when the watcher recovers
+    failing_since: Option<Instant>,
+}
+
+/// State of controllers that is exposed on /healthz and /readyz.
+/// Controllers are registered by their kind and report their state
+/// while they are starting and running
+#[derive(Default)]
+pub struct Health {
+    controllers: Mutex<BTreeMap<String, ControllerState>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, kind: &str, f: impl FnOnce(&mut ControllerState)) {
+        let mut controllers = self.controllers.lock().unwrap();
+        f(controllers.entry(kind.to_string()).or_default());
+    }
+
+    /// Register the controller, so it's not ready until it reports its state
+    pub(crate) fn register(&self, kind: &str) {
+        self.update(kind, |_| {});
+    }
+
+    pub(crate) fn set_client_connected(&self, kind: &str, connected: bool) {
+        self.update(kind, |state| state.client_connected = connected);
+    }
+
+    pub(crate) fn set_crds_installed(&self, kind: &str, installed: bool) {
+        self.update(kind, |state| state.crds_installed = installed);
+    }
+
+    /// The watcher has listed all the objects for the first time
+    pub(crate) fn set_synced(&self, kind: &str) {
+        self.update(kind, |state| state.synced = true);
+    }
+
+    /// The controller stream has ended and nothing will be reconciled anymore
+    pub(crate) fn set_stopped(&self, kind: &str) {
+        self.update(kind, |state| state.stopped = true);
+    }
+
+    pub(crate) fn watch_succeeded(&self, kind: &str) {
+        self.update(kind, |state| state.failing_since = None);
+    }
+
+    pub(crate) fn watch_failed(&self, kind: &str) {
+        self.update(kind, |state| {
+            state.failing_since.get_or_insert_with(Instant::now);
+        });
+    }
+
+    /// Problems that should stop the pod from receiving traffic
+    pub fn readiness(&self) -> Vec<String> {
+        let controllers = self.controllers.lock().unwrap();
+        let mut problems: Vec<String> = vec![];
+        for (kind, state) in controllers.iter() {
+            if !state.client_connected {
+                problems.push(format!("{}: kubernetes client is not connected", kind));
+            } else if !state.crds_installed {
+                problems.push(format!("{}: crd is not installed", kind));
+            } else if !state.synced {
+                problems.push(format!("{}: initial list is not synced yet", kind));
+            }
+        }
+        problems.extend(Self::stalled(&controllers));
+        problems
+    }
+
+    /// Problems that can only be fixed by restarting the controller
+    pub fn liveness(&self) -> Vec<String> {
+        Self::stalled(&self.controllers.lock().unwrap())
+    }
+
+    fn stalled(controllers: &BTreeMap<String, ControllerState>) -> Vec<String> {
+        let mut problems: Vec<String> = vec![];
+        for (kind, state) in controllers.iter() {
+            if state.stopped {
+                problems.push(format!("{}: controller has stopped", kind));
+            }
+            if let Some(since) = state.failing_since {
+                if since.elapsed() > STALLED_AFTER {
+                    problems.push(format!(
+                        "{}: watcher is failing for {}s",
+                        kind,
+                        since.elapsed().as_secs()
+                    ));
+                }
+            }
+        }
+        problems
+    }
+}
@@ -0,0 +1,412 @@
+//! Helm chart that is built from the same objects as plain manifests.
+//! Settings that can be changed on install are moved from objects to values.yaml,
+//! and objects refer to them with template expressions
+use crate::api::v1alpha1::clusterconfigsets_api::ClusterConfigSet;
+use crate::helpers::manifests::{
+    prepare_cluster_role, prepare_cluster_role_binding, prepare_configset_crd, prepare_deployment,
+    prepare_leader_election_role, prepare_leader_election_role_binding, prepare_namespaced_role,
+    prepare_namespaced_role_binding, prepare_pod_disruption_budget, prepare_service,
+    prepare_service_account, prepare_service_monitor, prepare_validating_webhook,
+    prepare_webhook_certificate, prepare_webhook_issuer, prepare_webhook_service, write_file,
+    ManifestsConfig,
+};
+use crate::Result;
+use kube::CustomResourceExt;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+/// List items with the prefix are replaced with template actions, like if and end
+static ACTION_PREFIX: &str = "#helm ";
+/// Objects are also rendered inside range loops, so the root context is used
+static RELEASE_NAMESPACE: &str = "{{ $.Release.Namespace }}";
+static IMAGE_REPOSITORY: &str = "{{ .Values.image.repository }}";
+static IMAGE_TAG: &str = "{{ .Values.image.tag }}";
+// Both are used inside `with` actions, so the value is the context
+static WATCH_NAMESPACES: &str = "{{ join \",\" . }}";
+static CONFIGSET_SELECTOR: &str = "{{ . }}";
+// Selectors may contain commas, spaces and `!`, so the whole argument is quoted
+static CONFIGSET_SELECTOR_ARG: &str = "{{ printf \"--configset-selector=%s\" . | quote }}";
+// Used inside range actions
+static WATCH_NAMESPACE: &str = "{{ . }}";
+static EXTRA_ARG: &str = "{{ toJson . }}";
+static CONTAINER: &str = "/spec/template/spec/containers/0";
+
+/// Top-level values with their descriptions, in the order of values.yaml
+static VALUES: &[(&str, &str)] = &[
+    ("image", "Image of the controller"),
+    (
+        "replicas",
+        "Number of controller replicas, only the elected leader is reconciling",
+    ),
+    (
+        "extraArgs",
+        "Flags that are passed to the controller, e.g. --resync-interval=600",
+    ),
+    ("resources", "Resources of the controller container"),
+    ("podSecurityContext", "Security context of controller pods"),
+    (
+        "securityContext",
+        "Security context of the controller container, nothing is written to the filesystem",
+    ),
+    (
+        "podAnnotations",
+        "Annotations of controller pods, metrics are annotated for Prometheus by default",
+    ),
+    (
+        "podDisruptionBudget",
+        "Only one replica is reconciling, so another one can be disrupted any time",
+    ),
+    (
+        "rbac",
+        "Only watch namespaces from watchNamespaces, Roles are created in them instead of the ClusterRole",
+    ),
+    (
+        "configsetSelector",
+        "Only reconcile ConfigSets matching the label selector",
+    ),
+    (
+        "webhook",
+        "Add the validating admission webhook, cert-manager is required in any case",
+    ),
+    (
+        "logging",
+        "Level is the value of RUST_LOG, format is either text or json",
+    ),
+    (
+        "metrics",
+        "Add a ServiceMonitor, the Prometheus operator must be installed",
+    ),
+];
+
+fn to_value<T: Serialize>(object: T) -> Value {
+    serde_json::to_value(object).unwrap()
+}
+
+fn action(action: &str) -> Value {
+    Value::String(format!("{}{}", ACTION_PREFIX, action))
+}
+
+// Keys are paths like image.tag, missing sections are created
+fn set_value(values: &mut Map<String, Value>, key: &str, value: Value) {
+    match key.split_once('.') {
+        Some((section, key)) => {
+            let section = values
+                .entry(section)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(section) = section {
+                set_value(section, key, value);
+            }
+        }
+        None => {
+            values.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Move the value at the pointer of the object to values, and refer to it from the object.
+/// The default is used when the object doesn't have the value
+fn template_value(
+    object: &mut Value,
+    pointer: &str,
+    values: &mut Map<String, Value>,
+    key: &str,
+    default: Value,
+) {
+    let (parent, field) = pointer.rsplit_once('/').unwrap();
+    let parent = object
+        .pointer_mut(parent)
+        .and_then(Value::as_object_mut)
+        .unwrap();
+    let value = parent
+        .insert(
+            field.to_string(),
+            Value::String(format!("{{{{ toJson .Values.{} }}}}", key)),
+        )
+        .unwrap_or(default);
+    set_value(values, key, value);
+}
+
+fn env_pointer(deployment: &Value, name: &str) -> String {
+    let env = format!("{}/env", CONTAINER);
+    let index = deployment
+        .pointer(&env)
+        .and_then(Value::as_array)
+        .and_then(|vars| vars.iter().position(|var| var["name"] == name))
+        .unwrap();
+    format!("{}/{}/value", env, index)
+}
+
+/// Wrap the list item containing the placeholder with the action and `end`
+fn wrap_item(list: &mut Value, placeholder: &str, begin: &str) {
+    let Some(items) = list.as_array_mut() else {
+        return;
+    };
+    let position = items.iter().position(|item| match item.as_str() {
+        Some(item) => item.contains(placeholder),
+        None => false,
+    });
+    if let Some(index) = position {
+        items.insert(index + 1, action("{{- end }}"));
+        items.insert(index, action(begin));
+    }
+}
+
+/// Serialize the object as a template. Values moved to values.yaml are inserted
+/// as JSON or quoted by the template, so they must not be quoted again,
+/// and actions are put on their own lines
+fn to_template(object: &Value) -> String {
+    let yaml = serde_yaml::to_string(object).unwrap();
+    let values = Regex::new(r"'(\{\{ (toJson [^']*|[^']* \| quote )\}\})'").unwrap();
+    let actions = Regex::new(&format!(r"(?m)^[ ]*- '{}(.*)'$", ACTION_PREFIX)).unwrap();
+    let yaml = values.replace_all(&yaml, "${1}");
+    format!("---\n{}", actions.replace_all(&yaml, "${1}"))
+}
+
+fn prepare_deployment_template(config: &ManifestsConfig, values: &mut Map<String, Value>) -> Value {
+    let mut deployment = to_value(prepare_deployment(config));
+    template_value(
+        &mut deployment,
+        "/spec/replicas",
+        values,
+        "replicas",
+        json!(1),
+    );
+    template_value(
+        &mut deployment,
+        &format!("{}/imagePullPolicy", CONTAINER),
+        values,
+        "image.pullPolicy",
+        json!("IfNotPresent"),
+    );
+    template_value(
+        &mut deployment,
+        &format!("{}/resources", CONTAINER),
+        values,
+        "resources",
+        json!({}),
+    );
+    template_value(
+        &mut deployment,
+        "/spec/template/spec/securityContext",
+        values,
+        "podSecurityContext",
+        json!({}),
+    );
+    template_value(
+        &mut deployment,
+        &format!("{}/securityContext", CONTAINER),
+        values,
+        "securityContext",
+        json!({}),
+    );
+    template_value(
+        &mut deployment,
+        "/spec/template/metadata/annotations",
+        values,
+        "podAnnotations",
+        json!({}),
+    );
+    let pointer = env_pointer(&deployment, "RUST_LOG");
+    template_value(
+        &mut deployment,
+        &pointer,
+        values,
+        "logging.level",
+        json!("info"),
+    );
+    let pointer = env_pointer(&deployment, "SHOEBILL_LOG_FORMAT");
+    template_value(
+        &mut deployment,
+        &pointer,
+        values,
+        "logging.format",
+        json!("text"),
+    );
+    if let Some(args) = deployment.pointer_mut(&format!("{}/args", CONTAINER)) {
+        wrap_item(
+            args,
+            WATCH_NAMESPACES,
+            "{{- with .Values.rbac.watchNamespaces }}",
+        );
+        let selector_arg = format!("--configset-selector={}", CONFIGSET_SELECTOR);
+        if let Some(items) = args.as_array_mut() {
+            for item in items.iter_mut() {
+                if item.as_str() == Some(selector_arg.as_str()) {
+                    *item = json!(CONFIGSET_SELECTOR_ARG);
+                }
+            }
+        }
+        wrap_item(
+            args,
+            CONFIGSET_SELECTOR_ARG,
+            "{{- with .Values.configsetSelector }}",
+        );
+        wrap_item(args, EXTRA_ARG, "{{- range .Values.extraArgs }}");
+    }
+    deployment
+}
+
+fn prepare_values_file(values: &Map<String, Value>) -> String {
+    VALUES
+        .iter()
+        .map(|(key, description)| {
+            let value = json!({ *key: values.get(*key).cloned().unwrap_or_default() });
+            format!(
+                "# {}\n{}",
+                description,
+                serde_yaml::to_string(&value).unwrap()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Write the chart to the output directory, defaults of values are taken from the config
+pub(crate) fn write_chart(config: &ManifestsConfig, output: &Path) -> Result<()> {
+    let mut values: Map<String, Value> = Map::new();
+    set_value(&mut values, "image.repository", json!(config.image));
+    set_value(&mut values, "image.tag", json!(config.tag));
+    set_value(
+        &mut values,
+        "rbac.watchNamespaces",
+        json!(config.watch_namespaces),
+    );
+    set_value(
+        &mut values,
+        "configsetSelector",
+        json!(config.configset_selector.clone().unwrap_or_default()),
+    );
+    set_value(&mut values, "webhook.enabled", json!(config.webhook));
+    set_value(&mut values, "extraArgs", json!(config.controller_args));
+    set_value(
+        &mut values,
+        "metrics.serviceMonitor.enabled",
+        json!(config.service_monitor),
+    );
+
+    // Objects are built with templates in place of settings
+    let templated = ManifestsConfig {
+        namespace: RELEASE_NAMESPACE.to_string(),
+        image: IMAGE_REPOSITORY.to_string(),
+        tag: IMAGE_TAG.to_string(),
+        watch_namespaces: vec![WATCH_NAMESPACES.to_string()],
+        configset_selector: Some(CONFIGSET_SELECTOR.to_string()),
+        controller_args: vec![EXTRA_ARG.to_string()],
+        // The chart always serves both versions of ConfigSets, like manifests do by default.
+        // Certificates of webhooks are always issued then, so only the validating webhook
+        // can be switched off by values
+        conversion_webhook: true,
+        ..config.clone()
+    };
+    let namespace = templated.namespace.clone();
+
+    let crds = [
+        to_template(&to_value(prepare_configset_crd(namespace.clone()))),
+        to_template(&to_value(ClusterConfigSet::crd())),
+    ]
+    .concat();
+    let service_account = to_template(&to_value(prepare_service_account(namespace.clone())));
+    let rbac = [
+        "{{- if .Values.rbac.watchNamespaces }}\n",
+        "{{- range .Values.rbac.watchNamespaces }}\n",
+        &to_template(&to_value(prepare_namespaced_role(
+            WATCH_NAMESPACE.to_string(),
+        ))),
+        &to_template(&to_value(prepare_namespaced_role_binding(
+            namespace.clone(),
+            WATCH_NAMESPACE.to_string(),
+        ))),
+        "{{- end }}\n",
+        "{{- else }}\n",
+        &to_template(&to_value(prepare_cluster_role(namespace.clone()))),
+        &to_template(&to_value(prepare_cluster_role_binding(namespace.clone()))),
+        "{{- end }}\n",
+        &to_template(&to_value(prepare_leader_election_role(namespace.clone()))),
+        &to_template(&to_value(prepare_leader_election_role_binding(
+            namespace.clone(),
+        ))),
+    ]
+    .concat();
+    let webhook = [
+        to_template(&prepare_webhook_issuer(namespace.clone())),
+        to_template(&prepare_webhook_certificate(namespace.clone())),
+        to_template(&to_value(prepare_webhook_service(namespace.clone()))),
+        "{{- if .Values.webhook.enabled }}\n".to_string(),
+        to_template(&to_value(prepare_validating_webhook(namespace.clone()))),
+        "{{- end }}\n".to_string(),
+    ]
+    .concat();
+    let deployment = to_template(&prepare_deployment_template(&templated, &mut values));
+    let service = to_template(&to_value(prepare_service(namespace.clone())));
+    let mut pod_disruption_budget = to_value(prepare_pod_disruption_budget(namespace.clone()));
+    template_value(
+        &mut pod_disruption_budget,
+        "/spec/maxUnavailable",
+        &mut values,
+        "podDisruptionBudget.maxUnavailable",
+        json!(1),
+    );
+    let pod_disruption_budget = to_template(&pod_disruption_budget);
+    let mut service_monitor = prepare_service_monitor(namespace);
+    template_value(
+        &mut service_monitor,
+        "/spec/endpoints/0/interval",
+        &mut values,
+        "metrics.serviceMonitor.interval",
+        json!("30s"),
+    );
+    let service_monitor = [
+        "{{- if .Values.metrics.serviceMonitor.enabled }}\n",
+        &to_template(&service_monitor),
+        "{{- end }}\n",
+    ]
+    .concat();
+
+    let chart = json!({
+        "apiVersion": "v2",
+        "name": "shoebill",
+        "description": "Build Secrets and ConfigMaps from templates that use values of other Secrets and ConfigMaps",
+        "type": "application",
+        "version": env!("CARGO_PKG_VERSION"),
+        "appVersion": config.tag,
+    });
+    write_file(
+        &output.join("Chart.yaml"),
+        &serde_yaml::to_string(&chart).unwrap(),
+    )?;
+    write_file(&output.join("values.yaml"), &prepare_values_file(&values))?;
+    for (file, template) in [
+        ("crds.yaml", crds),
+        ("serviceaccount.yaml", service_account),
+        ("rbac.yaml", rbac),
+        ("webhook.yaml", webhook),
+        ("deployment.yaml", deployment),
+        ("service.yaml", service),
+        ("poddisruptionbudget.yaml", pod_disruption_budget),
+        ("servicemonitor.yaml", service_monitor),
+    ] {
+        write_file(&output.join("templates").join(file), &template)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selector_argument_is_quoted() {
+        let config = ManifestsConfig {
+            configset_selector: Some(CONFIGSET_SELECTOR.to_string()),
+            ..Default::default()
+        };
+        let template = to_template(&prepare_deployment_template(&config, &mut Map::new()));
+        assert!(
+            template.contains("- {{ printf \"--configset-selector=%s\" . | quote }}\n"),
+            "{}",
+            template
+        );
+    }
+}
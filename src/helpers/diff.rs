@@ -0,0 +1,198 @@
+//! Comparison of a local ConfigSet with its targets in the cluster, so changes
+//! can be reviewed before the ConfigSet is applied
+use crate::api::v1alpha1::configsets_api::{ConfigSet, Kinds};
+use crate::controllers::configsets_controller::{configmap_data, secret_data, TargetData};
+use crate::helpers::render::{find_local, load_configset, render_configset, LocalObjects};
+use crate::{Error, Result};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::{Api, Client, ResourceExt};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+
+/// Length of value hashes that are printed instead of values of Secrets
+static HASH_LENGTH: usize = 12;
+
+/// Change of a single key of a target. Keys are never removed by the controller,
+/// rendered keys are merged over the live data, so there are no removals
+enum KeyChange<'a> {
+    Added(&'a [u8]),
+    Changed(&'a [u8], &'a [u8]),
+}
+
+fn key_changes<'a>(
+    live: &TargetData<'a>,
+    desired: &TargetData<'a>,
+) -> Vec<(&'a str, KeyChange<'a>)> {
+    let mut changes: Vec<(&str, KeyChange)> = vec![];
+    for (key, value) in desired {
+        match live.get(key) {
+            None => changes.push((key, KeyChange::Added(value))),
+            Some(live) if live != value => changes.push((key, KeyChange::Changed(live, value))),
+            Some(_) => {}
+        }
+    }
+    changes
+}
+
+// Values of Secrets are only shown as a short hash, so they can still be compared
+fn printable_value(value: &[u8], hidden: bool) -> String {
+    if hidden {
+        let hash = format!("{:x}", Sha256::digest(value));
+        format!("sha256:{}", &hash[..HASH_LENGTH])
+    } else {
+        format!("{:?}", String::from_utf8_lossy(value))
+    }
+}
+
+fn print_target(
+    kind: &str,
+    namespace: &str,
+    name: &str,
+    live: Option<&TargetData>,
+    desired: &TargetData,
+    hidden: bool,
+) -> bool {
+    let empty = TargetData::new();
+    let changes = key_changes(live.unwrap_or(&empty), desired);
+    if live.is_some() && changes.is_empty() {
+        return false;
+    }
+    let created = if live.is_none() { " (created)" } else { "" };
+    println!("{} {}/{}{}", kind, namespace, name, created);
+    for (key, change) in changes {
+        match change {
+            KeyChange::Added(value) => println!("  + {}: {}", key, printable_value(value, hidden)),
+            KeyChange::Changed(live, value) => println!(
+                "  ~ {}: {} -> {}",
+                key,
+                printable_value(live, hidden),
+                printable_value(value, hidden)
+            ),
+        }
+    }
+    true
+}
+
+/// Get inputs and targets of the ConfigSet from the cluster, missing ones are skipped
+async fn fetch_objects(
+    client: Client,
+    namespace: &str,
+    confset: &ConfigSet,
+) -> Result<LocalObjects> {
+    let mut secret_names: BTreeSet<&str> = BTreeSet::new();
+    let mut configmap_names: BTreeSet<&str> = BTreeSet::new();
+    let references = confset
+        .spec
+        .inputs
+        .iter()
+        .map(|input| (&input.from.kind, &input.from.name))
+        .chain(
+            confset
+                .spec
+                .targets
+                .iter()
+                .map(|target| (&target.target.kind, &target.target.name)),
+        );
+    for (kind, name) in references {
+        match kind {
+            Kinds::Secret => secret_names.insert(name),
+            Kinds::ConfigMap => configmap_names.insert(name),
+        };
+    }
+
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), namespace);
+    let configmaps: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let mut objects = LocalObjects::default();
+    for name in secret_names {
+        if let Some(secret) = secrets.get_opt(name).await.map_err(Error::KubeError)? {
+            objects.secrets.push(secret);
+        }
+    }
+    for name in configmap_names {
+        if let Some(configmap) = configmaps.get_opt(name).await.map_err(Error::KubeError)? {
+            objects.configmaps.push(configmap);
+        }
+    }
+    Ok(objects)
+}
+
+/// Render the ConfigSet from the file with inputs from the current kube context,
+/// and print changes of targets by keys. Values of Secrets are hidden unless
+/// show_secrets is set. Returns true if any target would be changed
+pub async fn diff(configset: &str, namespace: Option<String>, show_secrets: bool) -> Result<bool> {
+    let mut confset = load_configset(configset)?;
+    let client = Client::try_default().await.map_err(Error::KubeError)?;
+    let namespace = namespace
+        .or_else(|| confset.namespace())
+        .unwrap_or_else(|| client.default_namespace().to_string());
+    confset.metadata.namespace = Some(namespace.clone());
+
+    let objects = fetch_objects(client, &namespace, &confset).await?;
+    let (secrets, configmaps) = render_configset(&confset, &objects)?;
+
+    let mut changed = 0;
+    for target in confset.spec.targets.iter() {
+        let name = &target.target.name;
+        let target_changed = match target.target.kind {
+            Kinds::Secret => match secrets.get(&target.name) {
+                Some(desired) => print_target(
+                    "Secret",
+                    &namespace,
+                    name,
+                    find_local(&objects.secrets, &namespace, name)
+                        .map(secret_data)
+                        .as_ref(),
+                    &secret_data(desired),
+                    !show_secrets,
+                ),
+                None => false,
+            },
+            Kinds::ConfigMap => match configmaps.get(&target.name) {
+                Some(desired) => print_target(
+                    "ConfigMap",
+                    &namespace,
+                    name,
+                    find_local(&objects.configmaps, &namespace, name)
+                        .map(configmap_data)
+                        .as_ref(),
+                    &configmap_data(desired),
+                    false,
+                ),
+                None => false,
+            },
+        };
+        if target_changed {
+            changed += 1;
+        }
+    }
+    if changed == 0 {
+        println!("no changes");
+    } else {
+        println!(
+            "{} of {} targets would be changed",
+            changed,
+            confset.spec.targets.len()
+        );
+    }
+    Ok(changed > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_only_in_live_targets_are_kept() {
+        let live = TargetData::from([("EXISTING", b"a".as_slice()), ("URL", b"old")]);
+        let desired = TargetData::from([
+            ("EXISTING", b"a".as_slice()),
+            ("URL", b"new"),
+            ("USER", b"u"),
+        ]);
+        let changes = key_changes(&live, &desired);
+        let keys: Vec<&str> = changes.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec!["URL", "USER"]);
+        assert!(matches!(changes[0].1, KeyChange::Changed(b"old", b"new")));
+        assert!(matches!(changes[1].1, KeyChange::Added(b"u")));
+    }
+}
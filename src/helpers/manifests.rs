@@ -1,48 +1,326 @@
-use std::{collections::BTreeMap, default};
+use std::collections::BTreeMap;
 
+use clap::ValueEnum;
 use k8s_openapi::{
     api::{
+        admissionregistration::v1::{
+            RuleWithOperations, ServiceReference, ValidatingWebhook,
+            ValidatingWebhookConfiguration, WebhookClientConfig,
+        },
         apps::v1::{Deployment, DeploymentSpec},
-        core::v1::{Container, EnvVar, PodSpec, PodTemplate, PodTemplateSpec, ServiceAccount},
-        rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleRef, Subject},
+        core::v1::{
+            Capabilities, Container, ContainerPort, EnvVar, EnvVarSource, HTTPGetAction,
+            ObjectFieldSelector, PodSecurityContext, PodSpec, PodTemplateSpec, Probe,
+            ResourceRequirements, SeccompProfile, SecretVolumeSource, SecurityContext, Service,
+            ServiceAccount, ServicePort, ServiceSpec, Volume, VolumeMount,
+        },
+        policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
+        rbac::v1::{
+            ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, RoleRef, Subject,
+        },
+    },
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+        CustomResourceConversion, CustomResourceDefinition,
+        ServiceReference as CrdServiceReference, WebhookClientConfig as CrdWebhookClientConfig,
+        WebhookConversion,
     },
-    apimachinery::pkg::apis::meta::v1::LabelSelector,
+    apimachinery::pkg::{
+        api::resource::Quantity, apis::meta::v1::LabelSelector, util::intstr::IntOrString,
+    },
+};
+use kube::{
+    core::{crd::merge_crds, ObjectMeta},
+    CustomResourceExt,
 };
-use kube::{core::ObjectMeta, CustomResourceExt, ResourceExt};
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
 
-use crate::api::v1alpha1::configsets_api::ConfigSet;
+use crate::api::v1alpha1::clusterconfigsets_api::ClusterConfigSet;
+use crate::api::v1alpha1::configsets_api as v1alpha1;
+use crate::api::v1beta1::configsets_api as v1beta1;
+use crate::helpers::chart;
+use crate::logging::LogFormat;
+use crate::{Error, Result};
 
-pub fn generate_kube_manifests(namespace: String, image: String, image_tag: String) {
-    print!("---\n{}", serde_yaml::to_string(&ConfigSet::crd()).unwrap());
-    print!(
-        "---\n{}",
-        serde_yaml::to_string(&prepare_cluster_role(namespace.clone())).unwrap()
-    );
-    print!(
-        "---\n{}",
-        serde_yaml::to_string(&prepare_service_account(namespace.clone())).unwrap()
-    );
-    print!(
-        "---\n{}",
-        serde_yaml::to_string(&prepare_cluster_role_binding(namespace.clone())).unwrap()
-    );
+static WEBHOOK_NAME: &str = "shoebill-webhook";
+static WEBHOOK_PORT: i32 = 8443;
+// Metrics are served on /metrics of the HTTP port
+static HTTP_PORT: i32 = 8080;
+static WEBHOOK_TLS_PATH: &str = "/tls";
+// The image doesn't set a user, so it's set for the pod
+static CONTROLLER_USER: i64 = 65532;
+
+/// Format of generated manifests
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ManifestsFormat {
+    /// A stream of YAML documents printed to stdout
+    Yaml,
+    /// A Helm chart directory, settings can be changed in values.yaml
+    Helm,
+    /// A Kustomize base directory with a file per object
+    Kustomize,
+}
+
+/// Settings of generated manifests
+#[derive(Clone, Debug)]
+pub struct ManifestsConfig {
+    /// Namespace of the controller
+    pub namespace: String,
+    pub image: String,
+    pub tag: String,
+    /// Add the validating admission webhook
+    pub webhook: bool,
+    /// Serve ConfigSets as v1beta1 too, objects are converted by the webhook.
+    /// It's enabled by default, so both versions are served
+    pub conversion_webhook: bool,
+    pub replicas: i32,
+    /// Namespaced Roles are generated for them instead of the ClusterRole
+    pub watch_namespaces: Vec<String>,
+    pub configset_selector: Option<String>,
+    /// Value of RUST_LOG
+    pub log_level: String,
+    pub log_format: LogFormat,
+    pub requests_cpu: String,
+    pub requests_memory: String,
+    pub limits_cpu: Option<String>,
+    pub limits_memory: Option<String>,
+    /// Add a ServiceMonitor for the Prometheus operator
+    pub service_monitor: bool,
+    /// Flags that are passed to the controller as they are
+    pub controller_args: Vec<String>,
+}
+
+impl Default for ManifestsConfig {
+    fn default() -> Self {
+        ManifestsConfig {
+            namespace: "default".to_string(),
+            image: "shoebill".to_string(),
+            tag: "latest".to_string(),
+            webhook: false,
+            conversion_webhook: true,
+            replicas: 1,
+            watch_namespaces: vec![],
+            configset_selector: None,
+            log_level: "info".to_string(),
+            log_format: LogFormat::Text,
+            requests_cpu: "50m".to_string(),
+            requests_memory: "64Mi".to_string(),
+            limits_cpu: None,
+            limits_memory: Some("256Mi".to_string()),
+            service_monitor: false,
+            controller_args: vec![],
+        }
+    }
+}
+
+impl ManifestsConfig {
+    /// Webhooks are served with certificates issued by cert-manager
+    pub fn serves_webhooks(&self) -> bool {
+        self.webhook || self.conversion_webhook
+    }
+}
+
+fn to_value<T: Serialize>(object: T) -> serde_json::Value {
+    serde_json::to_value(object).unwrap()
+}
+
+fn output_error(path: &Path, reason: impl ToString) -> Error {
+    Error::OutputError {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+pub(crate) fn write_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| output_error(dir, err))?;
+    }
+    fs::write(path, content).map_err(|err| output_error(path, err))
+}
+
+/// Objects of the installation with names of files they are written to
+pub(crate) fn prepare_manifests(config: &ManifestsConfig) -> Vec<(String, serde_json::Value)> {
+    let namespace = config.namespace.clone();
+    let configset_crd = if config.conversion_webhook {
+        prepare_configset_crd(namespace.clone())
+    } else {
+        v1alpha1::ConfigSet::crd()
+    };
+    let mut manifests = vec![
+        ("crd-configsets.yaml".to_string(), to_value(configset_crd)),
+        (
+            "crd-clusterconfigsets.yaml".to_string(),
+            to_value(ClusterConfigSet::crd()),
+        ),
+        (
+            "serviceaccount.yaml".to_string(),
+            to_value(prepare_service_account(namespace.clone())),
+        ),
+    ];
+    if config.watch_namespaces.is_empty() {
+        manifests.push((
+            "clusterrole.yaml".to_string(),
+            to_value(prepare_cluster_role(namespace.clone())),
+        ));
+        manifests.push((
+            "clusterrolebinding.yaml".to_string(),
+            to_value(prepare_cluster_role_binding(namespace.clone())),
+        ));
+    }
+    for watch_namespace in config.watch_namespaces.iter() {
+        manifests.push((
+            format!("role-{}.yaml", watch_namespace),
+            to_value(prepare_namespaced_role(watch_namespace.clone())),
+        ));
+        manifests.push((
+            format!("rolebinding-{}.yaml", watch_namespace),
+            to_value(prepare_namespaced_role_binding(
+                namespace.clone(),
+                watch_namespace.clone(),
+            )),
+        ));
+    }
+    manifests.push((
+        "leader-election-role.yaml".to_string(),
+        to_value(prepare_leader_election_role(namespace.clone())),
+    ));
+    manifests.push((
+        "leader-election-rolebinding.yaml".to_string(),
+        to_value(prepare_leader_election_role_binding(namespace.clone())),
+    ));
+    if config.serves_webhooks() {
+        manifests.push((
+            "webhook-issuer.yaml".to_string(),
+            prepare_webhook_issuer(namespace.clone()),
+        ));
+        manifests.push((
+            "webhook-certificate.yaml".to_string(),
+            prepare_webhook_certificate(namespace.clone()),
+        ));
+        manifests.push((
+            "webhook-service.yaml".to_string(),
+            to_value(prepare_webhook_service(namespace.clone())),
+        ));
+    }
+    if config.webhook {
+        manifests.push((
+            "validatingwebhookconfiguration.yaml".to_string(),
+            to_value(prepare_validating_webhook(namespace)),
+        ));
+    }
+    manifests.push((
+        "deployment.yaml".to_string(),
+        to_value(prepare_deployment(config)),
+    ));
+    manifests.push((
+        "service.yaml".to_string(),
+        to_value(prepare_service(config.namespace.clone())),
+    ));
+    manifests.push((
+        "poddisruptionbudget.yaml".to_string(),
+        to_value(prepare_pod_disruption_budget(config.namespace.clone())),
+    ));
+    if config.service_monitor {
+        manifests.push((
+            "servicemonitor.yaml".to_string(),
+            prepare_service_monitor(config.namespace.clone()),
+        ));
+    }
+    manifests
+}
+
+// Objects are written to separate files, that are listed as resources of the base
+fn write_kustomize_base(config: &ManifestsConfig, output: &Path) -> Result<()> {
+    let manifests = prepare_manifests(config);
+    let resources: Vec<&String> = manifests.iter().map(|(file, _)| file).collect();
+    let kustomization = json!({
+        "apiVersion": "kustomize.config.k8s.io/v1beta1",
+        "kind": "Kustomization",
+        "resources": resources,
+    });
+    write_file(
+        &output.join("kustomization.yaml"),
+        &serde_yaml::to_string(&kustomization).unwrap(),
+    )?;
+    for (file, object) in manifests.iter() {
+        write_file(&output.join(file), &serde_yaml::to_string(object).unwrap())?;
+    }
+    Ok(())
+}
+
+/// Print manifests to stdout, or write them to the output directory
+/// as a Helm chart or a Kustomize base
+pub fn generate_kube_manifests(
+    config: &ManifestsConfig,
+    format: ManifestsFormat,
+    output: &str,
+) -> Result<()> {
+    match format {
+        ManifestsFormat::Yaml => {
+            for (_, object) in prepare_manifests(config) {
+                print!("---\n{}", serde_yaml::to_string(&object).unwrap());
+            }
+            Ok(())
+        }
+        ManifestsFormat::Helm => chart::write_chart(config, Path::new(output)),
+        ManifestsFormat::Kustomize => write_kustomize_base(config, Path::new(output)),
+    }
+}
 
-    print!(
-        "---\n{}",
-        serde_yaml::to_string(&prepare_deployment(
-            namespace.clone(),
-            image.clone(),
-            image_tag.clone()
-        ))
-        .unwrap()
+// With the conversion webhook ConfigSets are served in all the versions,
+// but stored as v1alpha1 yet. Without it only v1alpha1 is served.
+// Storage version migration plan:
+// - this release: v1beta1 is served and converted by the webhook, v1alpha1 is stored
+// - next release: v1beta1 is stored, existing objects are re-written to migrate them,
+//   and v1alpha1 is removed from status.storedVersions of the CRD
+// - after that v1alpha1 is not served anymore
+pub(crate) fn prepare_configset_crd(namespace: String) -> CustomResourceDefinition {
+    let mut crd = merge_crds(
+        vec![v1alpha1::ConfigSet::crd(), v1beta1::ConfigSet::crd()],
+        "v1alpha1",
     )
+    .unwrap();
+
+    let mut annotations: BTreeMap<String, String> = BTreeMap::new();
+    annotations.insert(
+        "cert-manager.io/inject-ca-from".to_string(),
+        format!("{}/{}", namespace, WEBHOOK_NAME),
+    );
+    crd.metadata.annotations = Some(annotations);
+    crd.spec.conversion = Some(CustomResourceConversion {
+        strategy: "Webhook".to_string(),
+        webhook: Some(WebhookConversion {
+            client_config: Some(CrdWebhookClientConfig {
+                service: Some(CrdServiceReference {
+                    name: WEBHOOK_NAME.to_string(),
+                    namespace,
+                    path: Some("/convert/configsets".to_string()),
+                    port: Some(443),
+                }),
+                ..Default::default()
+            }),
+            conversion_review_versions: vec!["v1".to_string()],
+        }),
+    });
+    crd
 }
 
-fn prepare_cluster_role(namespace: String) -> ClusterRole {
-    let rules: Vec<PolicyRule> = vec![
+// ClusterConfigSets rules are only added when the controller manages
+// the whole cluster, otherwise the same rules are granted per namespace
+fn prepare_policy_rules(cluster_scoped: bool) -> Vec<PolicyRule> {
+    let resources = |suffix: &str| -> Vec<String> {
+        let mut resources = vec![format!("configsets{}", suffix)];
+        if cluster_scoped {
+            resources.push(format!("clusterconfigsets{}", suffix));
+        }
+        resources
+    };
+    let mut rules: Vec<PolicyRule> = vec![
         PolicyRule {
             api_groups: Some(vec!["shoebill.badhouseplants.net".to_string()]),
-            resources: Some(vec!["configsets".to_string()]),
+            resources: Some(resources("")),
             verbs: vec![
                 "get".to_string(),
                 "list".to_string(),
@@ -54,7 +332,13 @@ fn prepare_cluster_role(namespace: String) -> ClusterRole {
         },
         PolicyRule {
             api_groups: Some(vec!["shoebill.badhouseplants.net".to_string()]),
-            resources: Some(vec!["configsets/finalizers".to_string()]),
+            resources: Some(resources("/status")),
+            verbs: vec!["get".to_string(), "patch".to_string(), "update".to_string()],
+            ..Default::default()
+        },
+        PolicyRule {
+            api_groups: Some(vec!["shoebill.badhouseplants.net".to_string()]),
+            resources: Some(resources("/finalizers")),
             verbs: vec![
                 "get".to_string(),
                 "list".to_string(),
@@ -79,20 +363,75 @@ fn prepare_cluster_role(namespace: String) -> ClusterRole {
             ],
             ..Default::default()
         },
+        // Reconciliation results are published as events
+        PolicyRule {
+            api_groups: Some(vec!["events.k8s.io".to_string()]),
+            resources: Some(vec!["events".to_string()]),
+            verbs: vec!["create".to_string()],
+            ..Default::default()
+        },
     ];
+    if cluster_scoped {
+        // ClusterConfigSets select namespaces for targets by labels
+        rules.push(PolicyRule {
+            api_groups: Some(vec!["".to_string()]),
+            resources: Some(vec!["namespaces".to_string()]),
+            verbs: vec!["get".to_string(), "list".to_string(), "watch".to_string()],
+            ..Default::default()
+        });
+    }
+    rules
+}
 
+pub(crate) fn prepare_cluster_role(namespace: String) -> ClusterRole {
     ClusterRole {
         metadata: ObjectMeta {
             name: Some("shoebill-controller".to_string()),
             namespace: Some(namespace),
             ..Default::default()
         },
-        rules: Some(rules),
+        rules: Some(prepare_policy_rules(true)),
         ..Default::default()
     }
 }
 
-fn prepare_service_account(namespace: String) -> ServiceAccount {
+// Used instead of the ClusterRole, when only some namespaces are watched
+pub(crate) fn prepare_namespaced_role(watch_namespace: String) -> Role {
+    Role {
+        metadata: ObjectMeta {
+            name: Some("shoebill-controller".to_string()),
+            namespace: Some(watch_namespace),
+            ..Default::default()
+        },
+        rules: Some(prepare_policy_rules(false)),
+    }
+}
+
+pub(crate) fn prepare_namespaced_role_binding(
+    namespace: String,
+    watch_namespace: String,
+) -> RoleBinding {
+    RoleBinding {
+        metadata: ObjectMeta {
+            name: Some("shoebill-controller".to_string()),
+            namespace: Some(watch_namespace),
+            ..Default::default()
+        },
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_string(),
+            kind: "Role".to_string(),
+            name: "shoebill-controller".to_string(),
+        },
+        subjects: Some(vec![Subject {
+            kind: "ServiceAccount".to_string(),
+            name: "shoebill-controller".to_string(),
+            namespace: Some(namespace),
+            ..Default::default()
+        }]),
+    }
+}
+
+pub(crate) fn prepare_service_account(namespace: String) -> ServiceAccount {
     ServiceAccount {
         metadata: ObjectMeta {
             name: Some("shoebill-controller".to_string()),
@@ -103,7 +442,7 @@ fn prepare_service_account(namespace: String) -> ServiceAccount {
     }
 }
 
-fn prepare_cluster_role_binding(namespace: String) -> ClusterRoleBinding {
+pub(crate) fn prepare_cluster_role_binding(namespace: String) -> ClusterRoleBinding {
     ClusterRoleBinding {
         metadata: ObjectMeta {
             name: Some("shoebill-controller".to_string()),
@@ -124,18 +463,349 @@ fn prepare_cluster_role_binding(namespace: String) -> ClusterRoleBinding {
     }
 }
 
-fn prepare_deployment(namespace: String, image: String, image_tag: String) -> Deployment {
+// Leases are only needed in the namespace of the controller
+pub(crate) fn prepare_leader_election_role(namespace: String) -> Role {
+    Role {
+        metadata: ObjectMeta {
+            name: Some("shoebill-leader-election".to_string()),
+            namespace: Some(namespace),
+            ..Default::default()
+        },
+        rules: Some(vec![PolicyRule {
+            api_groups: Some(vec!["coordination.k8s.io".to_string()]),
+            resources: Some(vec!["leases".to_string()]),
+            verbs: vec![
+                "get".to_string(),
+                "create".to_string(),
+                "update".to_string(),
+            ],
+            ..Default::default()
+        }]),
+    }
+}
+
+pub(crate) fn prepare_leader_election_role_binding(namespace: String) -> RoleBinding {
+    RoleBinding {
+        metadata: ObjectMeta {
+            name: Some("shoebill-leader-election".to_string()),
+            namespace: Some(namespace.clone()),
+            ..Default::default()
+        },
+        role_ref: RoleRef {
+            api_group: "rbac.authorization.k8s.io".to_string(),
+            kind: "Role".to_string(),
+            name: "shoebill-leader-election".to_string(),
+        },
+        subjects: Some(vec![Subject {
+            kind: "ServiceAccount".to_string(),
+            name: "shoebill-controller".to_string(),
+            namespace: Some(namespace),
+            ..Default::default()
+        }]),
+    }
+}
+
+// Webhook certificates are managed by cert-manager, so it must be installed in the cluster
+// when any of webhooks is added
+pub(crate) fn prepare_webhook_issuer(namespace: String) -> serde_json::Value {
+    json!({
+        "apiVersion": "cert-manager.io/v1",
+        "kind": "Issuer",
+        "metadata": {
+            "name": WEBHOOK_NAME,
+            "namespace": namespace,
+        },
+        "spec": {
+            "selfSigned": {},
+        },
+    })
+}
+
+pub(crate) fn prepare_webhook_certificate(namespace: String) -> serde_json::Value {
+    json!({
+        "apiVersion": "cert-manager.io/v1",
+        "kind": "Certificate",
+        "metadata": {
+            "name": WEBHOOK_NAME,
+            "namespace": namespace,
+        },
+        "spec": {
+            "secretName": WEBHOOK_NAME,
+            "dnsNames": [
+                format!("{}.{}.svc", WEBHOOK_NAME, namespace),
+                format!("{}.{}.svc.cluster.local", WEBHOOK_NAME, namespace),
+            ],
+            "issuerRef": {
+                "kind": "Issuer",
+                "name": WEBHOOK_NAME,
+            },
+        },
+    })
+}
+
+fn controller_labels() -> BTreeMap<String, String> {
     let mut labels: BTreeMap<String, String> = BTreeMap::new();
     labels.insert("container".to_string(), "shoebill-controller".to_string());
+    labels
+}
+
+pub(crate) fn prepare_webhook_service(namespace: String) -> Service {
+    Service {
+        metadata: ObjectMeta {
+            name: Some(WEBHOOK_NAME.to_string()),
+            namespace: Some(namespace),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            selector: Some(controller_labels()),
+            ports: Some(vec![ServicePort {
+                name: Some("webhook".to_string()),
+                port: 443,
+                target_port: Some(IntOrString::Int(WEBHOOK_PORT)),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn prepare_validating_webhook(namespace: String) -> ValidatingWebhookConfiguration {
+    let mut annotations: BTreeMap<String, String> = BTreeMap::new();
+    annotations.insert(
+        "cert-manager.io/inject-ca-from".to_string(),
+        format!("{}/{}", namespace, WEBHOOK_NAME),
+    );
+
+    ValidatingWebhookConfiguration {
+        metadata: ObjectMeta {
+            name: Some(WEBHOOK_NAME.to_string()),
+            annotations: Some(annotations),
+            ..Default::default()
+        },
+        webhooks: Some(vec![
+            ValidatingWebhook {
+                name: "configsets.shoebill.badhouseplants.net".to_string(),
+                admission_review_versions: vec!["v1".to_string()],
+                client_config: WebhookClientConfig {
+                    service: Some(ServiceReference {
+                        name: WEBHOOK_NAME.to_string(),
+                        namespace: namespace.clone(),
+                        path: Some("/validate/configsets".to_string()),
+                        port: Some(443),
+                    }),
+                    ..Default::default()
+                },
+                failure_policy: Some("Fail".to_string()),
+                // v1beta1 objects are converted to v1alpha1 before they are sent to the webhook
+                match_policy: Some("Equivalent".to_string()),
+                side_effects: "None".to_string(),
+                rules: Some(vec![RuleWithOperations {
+                    api_groups: Some(vec!["shoebill.badhouseplants.net".to_string()]),
+                    api_versions: Some(vec!["v1alpha1".to_string()]),
+                    operations: Some(vec!["CREATE".to_string(), "UPDATE".to_string()]),
+                    resources: Some(vec!["configsets".to_string()]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+            ValidatingWebhook {
+                name: "clusterconfigsets.shoebill.badhouseplants.net".to_string(),
+                admission_review_versions: vec!["v1".to_string()],
+                client_config: WebhookClientConfig {
+                    service: Some(ServiceReference {
+                        name: WEBHOOK_NAME.to_string(),
+                        namespace,
+                        path: Some("/validate/clusterconfigsets".to_string()),
+                        port: Some(443),
+                    }),
+                    ..Default::default()
+                },
+                failure_policy: Some("Fail".to_string()),
+                side_effects: "None".to_string(),
+                rules: Some(vec![RuleWithOperations {
+                    api_groups: Some(vec!["shoebill.badhouseplants.net".to_string()]),
+                    api_versions: Some(vec!["v1alpha1".to_string()]),
+                    operations: Some(vec!["CREATE".to_string(), "UPDATE".to_string()]),
+                    resources: Some(vec!["clusterconfigsets".to_string()]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+        ]),
+    }
+}
+
+// Probe on the http port, liveness is allowed to fail longer than readiness,
+// so the controller is not restarted while it's still syncing
+fn http_probe(path: &str, failure_threshold: i32) -> Probe {
+    Probe {
+        http_get: Some(HTTPGetAction {
+            path: Some(path.to_string()),
+            port: IntOrString::String("http".to_string()),
+            ..Default::default()
+        }),
+        initial_delay_seconds: Some(5),
+        period_seconds: Some(10),
+        failure_threshold: Some(failure_threshold),
+        ..Default::default()
+    }
+}
+
+// Metrics and probes are served on the HTTP port
+pub(crate) fn prepare_service(namespace: String) -> Service {
+    Service {
+        metadata: ObjectMeta {
+            name: Some("shoebill-controller".to_string()),
+            namespace: Some(namespace),
+            labels: Some(controller_labels()),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            selector: Some(controller_labels()),
+            ports: Some(vec![ServicePort {
+                name: Some("http".to_string()),
+                port: HTTP_PORT,
+                target_port: Some(IntOrString::String("http".to_string())),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+// Only one replica is reconciling anyway, so drains are never blocked,
+// but with more replicas one of them is always available to take over
+pub(crate) fn prepare_pod_disruption_budget(namespace: String) -> PodDisruptionBudget {
+    PodDisruptionBudget {
+        metadata: ObjectMeta {
+            name: Some("shoebill-controller".to_string()),
+            namespace: Some(namespace),
+            ..Default::default()
+        },
+        spec: Some(PodDisruptionBudgetSpec {
+            max_unavailable: Some(IntOrString::Int(1)),
+            selector: Some(LabelSelector {
+                match_labels: Some(controller_labels()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+// The Prometheus operator must be installed in the cluster
+pub(crate) fn prepare_service_monitor(namespace: String) -> serde_json::Value {
+    json!({
+        "apiVersion": "monitoring.coreos.com/v1",
+        "kind": "ServiceMonitor",
+        "metadata": {
+            "name": "shoebill-controller",
+            "namespace": namespace,
+        },
+        "spec": {
+            "selector": {
+                "matchLabels": controller_labels(),
+            },
+            "endpoints": [{
+                "port": "http",
+                "path": "/metrics",
+                "interval": "30s",
+            }],
+        },
+    })
+}
+
+fn prepare_resources(config: &ManifestsConfig) -> ResourceRequirements {
+    let mut requests: BTreeMap<String, Quantity> = BTreeMap::new();
+    requests.insert("cpu".to_string(), Quantity(config.requests_cpu.clone()));
+    requests.insert(
+        "memory".to_string(),
+        Quantity(config.requests_memory.clone()),
+    );
+    let mut limits: BTreeMap<String, Quantity> = BTreeMap::new();
+    if let Some(cpu) = &config.limits_cpu {
+        limits.insert("cpu".to_string(), Quantity(cpu.clone()));
+    }
+    if let Some(memory) = &config.limits_memory {
+        limits.insert("memory".to_string(), Quantity(memory.clone()));
+    }
+    ResourceRequirements {
+        requests: Some(requests),
+        limits: Some(limits),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn prepare_deployment(config: &ManifestsConfig) -> Deployment {
+    let labels = controller_labels();
+
+    let mut args: Vec<String> = vec![
+        "controller".to_string(),
+        // Also prevents two controllers from reconciling during rolling updates
+        "--leader-election".to_string(),
+    ];
+    if config.serves_webhooks() {
+        args.push(format!("--webhook-cert={}/tls.crt", WEBHOOK_TLS_PATH));
+        args.push(format!("--webhook-key={}/tls.key", WEBHOOK_TLS_PATH));
+        args.push(format!("--webhook-port={}", WEBHOOK_PORT));
+    }
+    if !config.watch_namespaces.is_empty() {
+        args.push(format!(
+            "--watch-namespaces={}",
+            config.watch_namespaces.join(",")
+        ));
+    }
+    if let Some(selector) = &config.configset_selector {
+        args.push(format!("--configset-selector={}", selector));
+    }
+    args.extend(config.controller_args.iter().cloned());
+    let mut ports: Vec<ContainerPort> = vec![ContainerPort {
+        name: Some("http".to_string()),
+        container_port: HTTP_PORT,
+        ..Default::default()
+    }];
+    let mut volumes: Vec<Volume> = vec![];
+    let mut volume_mounts: Vec<VolumeMount> = vec![];
+    // The certificate is issued by cert-manager into the Secret named after the webhook
+    if config.serves_webhooks() {
+        ports.push(ContainerPort {
+            name: Some("webhook".to_string()),
+            container_port: WEBHOOK_PORT,
+            ..Default::default()
+        });
+        volumes.push(Volume {
+            name: "webhook-tls".to_string(),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(WEBHOOK_NAME.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        volume_mounts.push(VolumeMount {
+            name: "webhook-tls".to_string(),
+            mount_path: WEBHOOK_TLS_PATH.to_string(),
+            read_only: Some(true),
+            ..Default::default()
+        });
+    }
+
+    // Metrics are scraped by Prometheus configured with the common annotations
+    let mut annotations: BTreeMap<String, String> = BTreeMap::new();
+    annotations.insert("prometheus.io/scrape".to_string(), "true".to_string());
+    annotations.insert("prometheus.io/port".to_string(), HTTP_PORT.to_string());
+    annotations.insert("prometheus.io/path".to_string(), "/metrics".to_string());
 
     Deployment {
         metadata: ObjectMeta {
             name: Some("shoebill-controller".to_string()),
-            namespace: Some(namespace.clone()),
+            namespace: Some(config.namespace.clone()),
             ..Default::default()
         },
         spec: Some(DeploymentSpec {
-            replicas: Some(1),
+            replicas: Some(config.replicas),
             selector: LabelSelector {
                 match_labels: Some(labels.clone()),
                 ..Default::default()
@@ -143,23 +813,75 @@ fn prepare_deployment(namespace: String, image: String, image_tag: String) -> De
             template: PodTemplateSpec {
                 metadata: Some(ObjectMeta {
                     labels: Some(labels.clone()),
+                    annotations: Some(annotations),
                     ..Default::default()
                 }),
                 spec: Some(PodSpec {
                     automount_service_account_token: Some(true),
                     containers: vec![Container {
                         command: Some(vec!["/shoebill".to_string()]),
-                        args: Some(vec!["controller".to_string()]),
-                        image: Some(format!("{}:{}", image, image_tag)),
+                        args: Some(args),
+                        image: Some(format!("{}:{}", config.image, config.tag)),
                         image_pull_policy: Some("IfNotPresent".to_string()),
                         name: "shoebill-controller".to_string(),
-                        env: Some(vec![EnvVar {
-                            name: "RUST_LOG".to_string(),
-                            value: Some("info".to_string()),
+                        env: Some(vec![
+                            EnvVar {
+                                name: "RUST_LOG".to_string(),
+                                value: Some(config.log_level.clone()),
+                                ..Default::default()
+                            },
+                            EnvVar {
+                                name: "SHOEBILL_LOG_FORMAT".to_string(),
+                                value: Some(
+                                    config
+                                        .log_format
+                                        .to_possible_value()
+                                        .unwrap()
+                                        .get_name()
+                                        .to_string(),
+                                ),
+                                ..Default::default()
+                            },
+                            EnvVar {
+                                name: "SHOEBILL_LEASE_IDENTITY".to_string(),
+                                value_from: Some(EnvVarSource {
+                                    field_ref: Some(ObjectFieldSelector {
+                                        field_path: "metadata.name".to_string(),
+                                        ..Default::default()
+                                    }),
+                                    ..Default::default()
+                                }),
+                                ..Default::default()
+                            },
+                        ]),
+                        ports: Some(ports),
+                        liveness_probe: Some(http_probe("/healthz", 6)),
+                        readiness_probe: Some(http_probe("/readyz", 3)),
+                        volume_mounts: (!volume_mounts.is_empty()).then_some(volume_mounts),
+                        resources: Some(prepare_resources(config)),
+                        // Nothing is written to the filesystem by the controller
+                        security_context: Some(SecurityContext {
+                            allow_privilege_escalation: Some(false),
+                            read_only_root_filesystem: Some(true),
+                            capabilities: Some(Capabilities {
+                                drop: Some(vec!["ALL".to_string()]),
+                                ..Default::default()
+                            }),
                             ..Default::default()
-                        }]),
+                        }),
                         ..Default::default()
                     }],
+                    security_context: Some(PodSecurityContext {
+                        run_as_non_root: Some(true),
+                        run_as_user: Some(CONTROLLER_USER),
+                        run_as_group: Some(CONTROLLER_USER),
+                        seccomp_profile: Some(SeccompProfile {
+                            type_: "RuntimeDefault".to_string(),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    volumes: (!volumes.is_empty()).then_some(volumes),
                     service_account_name: Some("shoebill-controller".to_string()),
                     ..Default::default()
                 }),
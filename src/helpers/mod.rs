@@ -1 +1,5 @@
-pub(crate) mod manifests;
+pub mod chart;
+pub mod diff;
+pub mod manifests;
+pub mod render;
+pub mod validate;
@@ -0,0 +1,265 @@
+//! Rendering of ConfigSets from local files, so they can be tested without a cluster
+use crate::api::v1alpha1::configsets_api::{ConfigSet, Kinds};
+use crate::api::v1beta1::configsets_api as v1beta1;
+use crate::controllers::configsets_controller::{
+    build_templates, configmap_input_value, input_not_found, secret_input_value,
+};
+use crate::{Error, Result};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use k8s_openapi::ByteString;
+use kube::core::ObjectMeta;
+use kube::{Resource, ResourceExt};
+use log::*;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+static MASKED: &str = "**MASKED**";
+/// Namespace of objects that don't have it in files
+static DEFAULT_NAMESPACE: &str = "default";
+
+/// Secrets and ConfigMaps that are read from local files
+#[derive(Clone, Debug, Default)]
+pub struct LocalObjects {
+    pub secrets: Vec<Secret>,
+    pub configmaps: Vec<ConfigMap>,
+}
+
+fn invalid_file(path: &str, reason: impl ToString) -> Error {
+    Error::InvalidFile {
+        path: path.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+pub(crate) fn read_documents(path: &str) -> Result<Vec<serde_yaml::Value>> {
+    let content = std::fs::read_to_string(path).map_err(|err| invalid_file(path, err))?;
+    let mut documents: Vec<serde_yaml::Value> = vec![];
+    for document in serde_yaml::Deserializer::from_str(&content) {
+        let value =
+            serde_yaml::Value::deserialize(document).map_err(|err| invalid_file(path, err))?;
+        if !value.is_null() {
+            documents.push(value);
+        }
+    }
+    Ok(documents)
+}
+
+fn from_value<K: DeserializeOwned>(path: &str, value: serde_yaml::Value) -> Result<K> {
+    serde_yaml::from_value(value).map_err(|err| invalid_file(path, err))
+}
+
+fn api_version(value: &serde_yaml::Value) -> Option<&str> {
+    value.get("apiVersion").and_then(|version| version.as_str())
+}
+
+fn kind(value: &serde_yaml::Value) -> Option<&str> {
+    value.get("kind").and_then(|kind| kind.as_str())
+}
+
+/// Read a ConfigSet from a YAML file, both API versions are accepted,
+/// and v1beta1 objects are converted to v1alpha1 like by the webhook
+pub fn load_configset(path: &str) -> Result<ConfigSet> {
+    let mut documents = read_documents(path)?;
+    if documents.len() != 1 {
+        return Err(invalid_file(path, "exactly one ConfigSet is expected"));
+    }
+    let document = documents.remove(0);
+    if kind(&document) != Some(ConfigSet::kind(&()).as_ref()) {
+        return Err(invalid_file(path, "kind must be ConfigSet"));
+    }
+    if api_version(&document) == Some(v1beta1::ConfigSet::api_version(&()).as_ref()) {
+        let confset: v1beta1::ConfigSet = from_value(path, document)?;
+        Ok(confset.into())
+    } else {
+        from_value(path, document)
+    }
+}
+
+// stringData is merged into data like by the API server, values of stringData win
+fn fold_string_data(mut secret: Secret) -> Secret {
+    if let Some(string_data) = secret.string_data.take() {
+        let mut data = secret.data.take().unwrap_or_default();
+        for (key, value) in string_data {
+            data.insert(key, ByteString(value.into_bytes()));
+        }
+        secret.data = Some(data);
+    }
+    secret
+}
+
+/// Read Secrets and ConfigMaps from YAML files, a file may contain many documents,
+/// and objects of other kinds are ignored. Secrets are read as they would be
+/// stored in the cluster, so stringData is merged into data
+pub fn load_objects(paths: &[String]) -> Result<LocalObjects> {
+    let mut objects = LocalObjects::default();
+    for path in paths {
+        for document in read_documents(path)? {
+            match kind(&document) {
+                Some("Secret") => objects
+                    .secrets
+                    .push(fold_string_data(from_value(path, document)?)),
+                Some("ConfigMap") => objects.configmaps.push(from_value(path, document)?),
+                kind => warn!(
+                    "{}: {} is ignored",
+                    path,
+                    kind.unwrap_or("object without kind")
+                ),
+            }
+        }
+    }
+    Ok(objects)
+}
+
+// Objects without a namespace belong to the namespace of the ConfigSet
+pub(crate) fn find_local<'a, K: Resource>(
+    objects: &'a [K],
+    namespace: &str,
+    name: &str,
+) -> Option<&'a K> {
+    objects.iter().find(|object| {
+        let same_namespace = match object.namespace() {
+            Some(object_namespace) => object_namespace == namespace,
+            None => true,
+        };
+        object.name_any() == name && same_namespace
+    })
+}
+
+/// Render targets of the ConfigSet with inputs from local objects, the same way
+/// the controller does. Targets that are not in local objects are created empty
+pub fn render_configset(
+    confset: &ConfigSet,
+    objects: &LocalObjects,
+) -> Result<(HashMap<String, Secret>, HashMap<String, ConfigMap>)> {
+    confset
+        .spec
+        .validate()
+        .map_err(|err| Error::IllegalConfigSet(Box::new(err)))?;
+    let namespace = confset
+        .namespace()
+        .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
+
+    let mut inputs: HashMap<String, String> = HashMap::new();
+    for input in confset.spec.inputs.iter() {
+        let value = match input.from.kind {
+            Kinds::Secret => match find_local(&objects.secrets, &namespace, &input.from.name) {
+                Some(secret) => secret_input_value(secret, &input.from),
+                None => Err(input_not_found(&input.from)),
+            },
+            Kinds::ConfigMap => {
+                match find_local(&objects.configmaps, &namespace, &input.from.name) {
+                    Some(configmap) => configmap_input_value(configmap, &input.from),
+                    None => Err(input_not_found(&input.from)),
+                }
+            }
+        }?;
+        inputs.insert(input.name.clone(), value);
+    }
+
+    let mut target_secrets: HashMap<String, Secret> = HashMap::new();
+    let mut target_configmaps: HashMap<String, ConfigMap> = HashMap::new();
+    for target in confset.spec.targets.iter() {
+        let metadata = ObjectMeta {
+            name: Some(target.target.name.clone()),
+            namespace: Some(namespace.clone()),
+            ..Default::default()
+        };
+        match target.target.kind {
+            Kinds::Secret => {
+                let secret = find_local(&objects.secrets, &namespace, &target.target.name)
+                    .cloned()
+                    .unwrap_or_else(|| Secret {
+                        data: Some(BTreeMap::new()),
+                        metadata,
+                        ..Default::default()
+                    });
+                target_secrets.insert(target.name.clone(), secret);
+            }
+            Kinds::ConfigMap => {
+                let configmap = find_local(&objects.configmaps, &namespace, &target.target.name)
+                    .cloned()
+                    .unwrap_or_else(|| ConfigMap {
+                        data: Some(BTreeMap::new()),
+                        metadata,
+                        ..Default::default()
+                    });
+                target_configmaps.insert(target.name.clone(), configmap);
+            }
+        }
+    }
+
+    build_templates(
+        confset.spec.templates.clone(),
+        &mut target_secrets,
+        &mut target_configmaps,
+        confset.spec.targets.clone(),
+        inputs,
+        confset.name_any(),
+    )?;
+    Ok((target_secrets, target_configmaps))
+}
+
+// Values are moved to stringData, so they are readable, or masked
+fn printable_secret(mut secret: Secret, mask: bool) -> Secret {
+    let data = secret.data.take().unwrap_or_default();
+    let string_data = data
+        .into_iter()
+        .map(|(key, value)| {
+            let value = if mask {
+                MASKED.to_string()
+            } else {
+                String::from_utf8_lossy(&value.0).to_string()
+            };
+            (key, value)
+        })
+        .collect();
+    secret.string_data = Some(string_data);
+    secret
+}
+
+/// Render the ConfigSet from the file with inputs from the files,
+/// and print the targets as YAML documents
+pub fn render(configset: &str, inputs: &[String], mask: bool) -> Result<()> {
+    let confset = load_configset(configset)?;
+    let objects = load_objects(inputs)?;
+    let (secrets, configmaps) = render_configset(&confset, &objects)?;
+    // Targets are printed in the order of the spec
+    for target in confset.spec.targets.iter() {
+        let document = match target.target.kind {
+            Kinds::Secret => secrets
+                .get(&target.name)
+                .map(|secret| serde_yaml::to_string(&printable_secret(secret.clone(), mask))),
+            Kinds::ConfigMap => configmaps.get(&target.name).map(serde_yaml::to_string),
+        };
+        if let Some(document) = document {
+            let document = document.map_err(|err| invalid_file(configset, err))?;
+            print!("---\n{}", document);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_data_of_targets_is_kept() {
+        let confset = load_configset("tests/manifests/example.yaml").unwrap();
+        let objects = load_objects(&[
+            "tests/manifests/secret.yaml".to_string(),
+            "tests/manifests/configmap.yaml".to_string(),
+        ])
+        .unwrap();
+        let (secrets, _) = render_configset(&confset, &objects).unwrap();
+        let secret = secrets
+            .values()
+            .find(|secret| secret.name_any() == "database-secret")
+            .unwrap();
+        let data = secret.data.clone().unwrap();
+        assert_eq!(data["EXISTING"], ByteString(b"TEST".to_vec()));
+        // Keys from stringData of the local object are kept next to rendered ones
+        assert_eq!(data["PASSWORD"], ByteString(b"123123!!".to_vec()));
+    }
+}
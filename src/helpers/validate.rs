@@ -0,0 +1,702 @@
+//! Static checks of ConfigSet and ClusterConfigSet manifests, so problems
+//! are found in CI before they are applied to a cluster
+use crate::api::v1alpha1::clusterconfigsets_api::ClusterConfigSet;
+use crate::api::v1alpha1::configsets_api::{
+    ConfigSet, ConfigSetSpec, ValidationError, ValidationErrors,
+};
+use crate::api::v1beta1::configsets_api as v1beta1;
+use crate::helpers::render::read_documents;
+use crate::Result;
+use handlebars::template::{HelperTemplate, Parameter, TemplateElement};
+use handlebars::{Path, Template};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+    CustomResourceDefinition, JSONSchemaProps, JSONSchemaPropsOrArray,
+};
+use kube::{CustomResourceExt, Resource};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+/// Helpers that change the context of their blocks, so paths inside of them
+/// don't refer to inputs
+static CONTEXT_HELPERS: [&str; 2] = ["each", "with"];
+
+/// Rules that are checked, with their descriptions
+static RULES: [(&str, &str); 11] = [
+    (
+        "parse-error",
+        "The file can't be parsed as a ConfigSet or a ClusterConfigSet",
+    ),
+    (
+        "schema",
+        "The ConfigSet doesn't conform to the schema of the CRD",
+    ),
+    (
+        "template-parse",
+        "The template is not a valid Handlebars template",
+    ),
+    (
+        "undeclared-variable",
+        "The template uses a variable that is not declared in inputs",
+    ),
+    ("unused-input", "The input is not used by any template"),
+    (
+        "unknown-target",
+        "The template refers to a target that is not declared",
+    ),
+    ("duplicate-target", "The target name is used more than once"),
+    (
+        "duplicate-key",
+        "The key is written to the target by more than one template",
+    ),
+    (
+        "invalid-key",
+        "The key can't be used in Secrets and ConfigMaps",
+    ),
+    (
+        "invalid-refresh-interval",
+        "The refresh interval is not a valid duration",
+    ),
+    (
+        "invalid-selector",
+        "The namespace selector of the target can't be used to list namespaces",
+    ),
+];
+
+/// Format of the validation report
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// A line per problem
+    Human,
+    /// A JSON list of problems
+    Json,
+    /// SARIF 2.1.0, supported by code scanning tools
+    Sarif,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single problem found in a file
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    pub file: String,
+    /// ConfigSet or ClusterConfigSet, if the file could be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Name of the object, with the namespace of ConfigSets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub configset: Option<String>,
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+fn rule_of(error: &ValidationError) -> &'static str {
+    match error {
+        ValidationError::DuplicateTarget(_) => "duplicate-target",
+        ValidationError::UnknownTarget { .. } => "unknown-target",
+        ValidationError::InvalidTemplate { .. } => "template-parse",
+        ValidationError::DuplicateKey { .. } => "duplicate-key",
+        ValidationError::InvalidKey(_) => "invalid-key",
+        ValidationError::InvalidRefreshInterval(_) => "invalid-refresh-interval",
+        ValidationError::InvalidSelector { .. } => "invalid-selector",
+    }
+}
+
+/// Check all the ConfigSets and ClusterConfigSets in the files,
+/// other objects are ignored
+pub fn lint_files(files: &[String]) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+    for file in files {
+        let documents = match read_documents(file) {
+            Ok(documents) => documents,
+            Err(err) => {
+                findings.push(Finding {
+                    file: file.clone(),
+                    kind: None,
+                    configset: None,
+                    rule: "parse-error",
+                    severity: Severity::Error,
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+        for document in documents {
+            let document: Value = match serde_json::to_value(document) {
+                Ok(document) => document,
+                Err(err) => {
+                    findings.push(Finding {
+                        file: file.clone(),
+                        kind: None,
+                        configset: None,
+                        rule: "parse-error",
+                        severity: Severity::Error,
+                        message: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let kind = document.get("kind").and_then(Value::as_str);
+            if kind == Some(&ConfigSet::kind(&())) || kind == Some(&ClusterConfigSet::kind(&())) {
+                findings.extend(lint_document(file, document));
+            }
+        }
+    }
+    findings
+}
+
+fn lint_document(file: &str, document: Value) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+    let kind = document
+        .get("kind")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let mut finding = |configset: Option<String>, rule, severity, message| {
+        findings.push(Finding {
+            file: file.to_string(),
+            kind: kind.clone(),
+            configset,
+            rule,
+            severity,
+            message,
+        })
+    };
+    let cluster = kind.as_deref() == Some(&ClusterConfigSet::kind(&()));
+    let beta = document.get("apiVersion").and_then(Value::as_str)
+        == Some(&v1beta1::ConfigSet::api_version(&()));
+    let crd = if cluster {
+        ClusterConfigSet::crd()
+    } else if beta {
+        v1beta1::ConfigSet::crd()
+    } else {
+        ConfigSet::crd()
+    };
+    let name = document
+        .pointer("/metadata/name")
+        .and_then(Value::as_str)
+        .map(|name| {
+            match document
+                .pointer("/metadata/namespace")
+                .and_then(Value::as_str)
+            {
+                Some(namespace) => format!("{}/{}", namespace, name),
+                None => name.to_string(),
+            }
+        });
+    // Only the spec is validated, metadata is checked by the API server itself
+    let mut problems: Vec<String> = vec![];
+    if let Some(schema) =
+        crd_schema(&crd).and_then(|schema| schema.properties.as_ref()?.get("spec"))
+    {
+        match document.get("spec") {
+            Some(spec) => check_schema(schema, spec, ".spec", &mut problems),
+            None => problems.push(".spec: is required".to_string()),
+        }
+    }
+    let schema_conforms = problems.is_empty();
+    for problem in problems {
+        finding(name.clone(), "schema", Severity::Error, problem);
+    }
+    // Templates of ClusterConfigSets are checked like the ones of ConfigSets,
+    // their validation also checks namespace selectors
+    let checked = |spec: ConfigSetSpec| {
+        let validated = spec.validate();
+        (spec, validated)
+    };
+    let parsed: std::result::Result<(ConfigSetSpec, std::result::Result<(), ValidationErrors>), _> =
+        if cluster {
+            serde_json::from_value::<ClusterConfigSet>(document)
+                .map(|cconfset| (cconfset.spec.to_configset_spec(), cconfset.spec.validate()))
+        } else if beta {
+            serde_json::from_value::<v1beta1::ConfigSet>(document)
+                .map(|confset| checked(ConfigSet::from(confset).spec))
+        } else {
+            serde_json::from_value::<ConfigSet>(document).map(|confset| checked(confset.spec))
+        };
+    let (spec, validated) = match parsed {
+        Ok(parsed) => parsed,
+        // Problems that are found by the schema make the parsing fail too
+        Err(err) => {
+            if schema_conforms {
+                finding(name, "parse-error", Severity::Error, err.to_string());
+            }
+            return findings;
+        }
+    };
+    if let Err(errors) = validated {
+        for error in errors.0 {
+            finding(
+                name.clone(),
+                rule_of(&error),
+                Severity::Error,
+                error.to_string(),
+            );
+        }
+    }
+    for (rule, severity, message) in check_variables(&spec) {
+        finding(name.clone(), rule, severity, message);
+    }
+    findings
+}
+
+fn crd_schema(crd: &CustomResourceDefinition) -> Option<&JSONSchemaProps> {
+    crd.spec
+        .versions
+        .first()
+        .and_then(|version| version.schema.as_ref())
+        .and_then(|schema| schema.open_api_v3_schema.as_ref())
+}
+
+/// Structural validation, like the API server does. CEL rules are not evaluated,
+/// but the rule of ConfigSets is also checked by the spec validation
+fn check_schema(schema: &JSONSchemaProps, value: &Value, path: &str, problems: &mut Vec<String>) {
+    let at = if path.is_empty() { "." } else { path };
+    if value.is_null() {
+        if schema.nullable != Some(true) {
+            problems.push(format!("{}: must not be null", at));
+        }
+        return;
+    }
+    let type_matches = match schema.type_.as_deref() {
+        Some("object") => value.is_object(),
+        Some("array") => value.is_array(),
+        Some("string") => value.is_string(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        _ => true,
+    };
+    if !type_matches {
+        problems.push(format!(
+            "{}: must be {}",
+            at,
+            schema.type_.clone().unwrap_or_default()
+        ));
+        return;
+    }
+    if let Some(allowed) = &schema.enum_ {
+        if !allowed.iter().any(|allowed| &allowed.0 == value) {
+            let allowed: Vec<String> = allowed
+                .iter()
+                .map(|allowed| allowed.0.to_string())
+                .collect();
+            problems.push(format!("{}: must be one of {}", at, allowed.join(", ")));
+        }
+    }
+    match value {
+        Value::Object(object) => {
+            for required in schema.required.iter().flatten() {
+                if !object.contains_key(required) {
+                    problems.push(format!("{}: {} is required", at, required));
+                }
+            }
+            if let Some(properties) = &schema.properties {
+                for (key, value) in object {
+                    let path = format!("{}.{}", path, key);
+                    match properties.get(key) {
+                        Some(property) => check_schema(property, value, &path, problems),
+                        None if schema.x_kubernetes_preserve_unknown_fields != Some(true) => {
+                            problems.push(format!("{}: unknown field", path))
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(max) = schema.max_items {
+                if items.len() as i64 > max {
+                    problems.push(format!("{}: must have at most {} items", at, max));
+                }
+            }
+            if let Some(JSONSchemaPropsOrArray::Schema(item_schema)) = &schema.items {
+                for (index, item) in items.iter().enumerate() {
+                    check_schema(item_schema, item, &format!("{}[{}]", path, index), problems);
+                }
+            }
+        }
+        Value::String(string) => {
+            let length = string.chars().count() as i64;
+            if schema.min_length.is_some_and(|min| length < min) {
+                problems.push(format!(
+                    "{}: must be at least {} characters long",
+                    at,
+                    schema.min_length.unwrap_or_default()
+                ));
+            }
+            if schema.max_length.is_some_and(|max| length > max) {
+                problems.push(format!(
+                    "{}: must be at most {} characters long",
+                    at,
+                    schema.max_length.unwrap_or_default()
+                ));
+            }
+            if let Some(pattern) = &schema.pattern {
+                match Regex::new(pattern) {
+                    Ok(regex) if !regex.is_match(string) => {
+                        problems.push(format!("{}: must match {}", at, pattern))
+                    }
+                    Ok(_) => {}
+                    // The API server may still accept it, but it can't be checked here
+                    Err(err) => problems.push(format!(
+                        "{}: the pattern {} of the schema can't be checked: {}",
+                        at, pattern, err
+                    )),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Compare variables used by templates with declared inputs
+fn check_variables(spec: &ConfigSetSpec) -> Vec<(&'static str, Severity, String)> {
+    let declared: BTreeSet<&str> = spec
+        .inputs
+        .iter()
+        .map(|input| input.name.as_str())
+        .collect();
+    let mut used: BTreeSet<String> = BTreeSet::new();
+    let mut problems: Vec<(&'static str, Severity, String)> = vec![];
+    for template in spec.templates.iter() {
+        // Parse errors are already reported by the spec validation
+        let Ok(compiled) = Template::compile(template.template.as_str()) else {
+            continue;
+        };
+        let mut variables: BTreeSet<String> = BTreeSet::new();
+        template_variables(&compiled, &mut variables);
+        for variable in variables.iter() {
+            if !declared.contains(variable.as_str()) {
+                problems.push((
+                    "undeclared-variable",
+                    Severity::Error,
+                    format!(
+                        "template {} uses {} that is not declared in inputs",
+                        template.name, variable
+                    ),
+                ));
+            }
+        }
+        used.extend(variables);
+    }
+    for input in spec.inputs.iter() {
+        if !used.contains(&input.name) {
+            problems.push((
+                "unused-input",
+                Severity::Warning,
+                format!("input {} is not used by any template", input.name),
+            ));
+        }
+    }
+    problems
+}
+
+fn template_variables(template: &Template, variables: &mut BTreeSet<String>) {
+    for element in template.elements.iter() {
+        element_variables(element, variables);
+    }
+}
+
+fn element_variables(element: &TemplateElement, variables: &mut BTreeSet<String>) {
+    match element {
+        TemplateElement::Expression(helper) | TemplateElement::HtmlExpression(helper) => {
+            if helper.params.is_empty() && helper.hash.is_empty() {
+                // Without parameters, the expression is a variable
+                match &helper.name {
+                    Parameter::Name(name) => add_variable(name, variables),
+                    parameter => parameter_variables(parameter, variables),
+                }
+            } else {
+                helper_variables(helper, variables);
+            }
+        }
+        TemplateElement::HelperBlock(helper) => {
+            helper_variables(helper, variables);
+            let changes_context = match &helper.name {
+                Parameter::Name(name) => CONTEXT_HELPERS.contains(&name.as_str()),
+                _ => false,
+            };
+            if let Some(template) = &helper.template {
+                if !changes_context {
+                    template_variables(template, variables);
+                }
+            }
+            if let Some(inverse) = &helper.inverse {
+                template_variables(inverse, variables);
+            }
+        }
+        TemplateElement::DecoratorExpression(decorator)
+        | TemplateElement::DecoratorBlock(decorator)
+        | TemplateElement::PartialExpression(decorator)
+        | TemplateElement::PartialBlock(decorator) => {
+            for parameter in decorator.params.iter().chain(decorator.hash.values()) {
+                parameter_variables(parameter, variables);
+            }
+        }
+        TemplateElement::RawString(_) | TemplateElement::Comment(_) => {}
+    }
+}
+
+fn helper_variables(helper: &HelperTemplate, variables: &mut BTreeSet<String>) {
+    for parameter in helper.params.iter().chain(helper.hash.values()) {
+        parameter_variables(parameter, variables);
+    }
+}
+
+fn parameter_variables(parameter: &Parameter, variables: &mut BTreeSet<String>) {
+    match parameter {
+        Parameter::Path(Path::Relative((_, raw))) => add_variable(raw, variables),
+        Parameter::Subexpression(subexpression) => {
+            element_variables(subexpression.as_element(), variables)
+        }
+        Parameter::Path(Path::Local(_)) | Parameter::Name(_) | Parameter::Literal(_) => {}
+    }
+}
+
+// Only the first segment of a path is an input, e.g. `config.host` uses `config`.
+// Blocks that change the context are skipped, so `this` and `@root` are inputs
+// here too, while other data variables and parent contexts are not
+fn add_variable(raw: &str, variables: &mut BTreeSet<String>) {
+    let path = ["this.", "this/", "./", "@root.", "@root/"]
+        .iter()
+        .find_map(|prefix| raw.strip_prefix(prefix))
+        .unwrap_or(raw);
+    if path.starts_with('@') || path.starts_with("..") || path == "this" {
+        return;
+    }
+    if let Some(first) = path.split(['.', '/']).next() {
+        if !first.is_empty() {
+            variables.insert(first.to_string());
+        }
+    }
+}
+
+fn sarif(findings: &[Finding]) -> Value {
+    let rules: Vec<Value> = RULES
+        .iter()
+        .map(|(id, description)| json!({"id": id, "shortDescription": {"text": description}}))
+        .collect();
+    let results: Vec<Value> = findings
+        .iter()
+        .map(|finding| {
+            let message = match (&finding.kind, &finding.configset) {
+                (Some(kind), Some(configset)) => {
+                    format!("{} {}: {}", kind, configset, finding.message)
+                }
+                _ => finding.message.clone(),
+            };
+            json!({
+                "ruleId": finding.rule,
+                "level": finding.severity,
+                "message": {"text": message},
+                "locations": [{
+                    "physicalLocation": {"artifactLocation": {"uri": finding.file}},
+                }],
+            })
+        })
+        .collect();
+    json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": {"driver": {"name": "shoebill", "rules": rules}},
+            "results": results,
+        }],
+    })
+}
+
+/// Check the files and print the report, returns false if any error is found.
+/// Warnings don't fail the validation
+pub fn validate(files: &[String], format: ReportFormat) -> Result<bool> {
+    let findings = lint_files(files);
+    match format {
+        ReportFormat::Human => {
+            for finding in findings.iter() {
+                let severity = match finding.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                };
+                match (&finding.kind, &finding.configset) {
+                    (Some(kind), Some(configset)) => println!(
+                        "{}: {}[{}] {} {}: {}",
+                        finding.file, severity, finding.rule, kind, configset, finding.message
+                    ),
+                    _ => println!(
+                        "{}: {}[{}] {}",
+                        finding.file, severity, finding.rule, finding.message
+                    ),
+                }
+            }
+        }
+        ReportFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&findings).map_err(crate::Error::SerializationError)?
+        ),
+        ReportFormat::Sarif => println!(
+            "{}",
+            serde_json::to_string_pretty(&sarif(&findings))
+                .map_err(crate::Error::SerializationError)?
+        ),
+    }
+    Ok(!findings
+        .iter()
+        .any(|finding| finding.severity == Severity::Error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::v1alpha1::configsets_api::{Input, InputWithName, Kinds, Templates};
+    use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::JSONSchemaProps;
+
+    fn fixture(rule: &str) -> String {
+        format!("tests/lint/{}.yaml", rule)
+    }
+
+    #[test]
+    fn every_rule_has_a_fixture() {
+        for (rule, _) in RULES {
+            let findings = lint_files(&[fixture(rule)]);
+            assert!(
+                findings.iter().any(|finding| finding.rule == rule),
+                "{}: {:?}",
+                rule,
+                findings
+            );
+        }
+    }
+
+    #[test]
+    fn examples_are_valid() {
+        let files: Vec<String> = [
+            "example.yaml",
+            "example-v1beta1.yaml",
+            "cluster-example.yaml",
+        ]
+        .iter()
+        .map(|file| format!("tests/manifests/{}", file))
+        .collect();
+        let findings = lint_files(&files);
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn sarif_report_matches_the_snapshot() {
+        let findings = lint_files(&[fixture("undeclared-variable"), fixture("unused-input")]);
+        let snapshot: Value =
+            serde_json::from_str(include_str!("../../tests/lint/report.sarif")).unwrap();
+        assert_eq!(sarif(&findings), snapshot);
+    }
+
+    #[test]
+    fn only_errors_fail_the_validation() {
+        assert!(validate(&[fixture("unused-input")], ReportFormat::Json).unwrap());
+        assert!(!validate(&[fixture("undeclared-variable")], ReportFormat::Json).unwrap());
+        assert!(!validate(&[fixture("parse-error")], ReportFormat::Sarif).unwrap());
+    }
+
+    fn variables(template: &str) -> BTreeSet<String> {
+        let mut variables = BTreeSet::new();
+        template_variables(&Template::compile(template).unwrap(), &mut variables);
+        variables
+    }
+
+    fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn blocks_changing_the_context_only_use_their_parameters() {
+        assert_eq!(
+            variables("{{#each HOSTS}}{{name}}:{{@index}}{{/each}}"),
+            set(&["HOSTS"])
+        );
+        assert_eq!(
+            variables("{{#with CONFIG}}{{host}}{{else}}{{DEFAULT}}{{/with}}"),
+            set(&["CONFIG", "DEFAULT"])
+        );
+        assert_eq!(
+            variables("{{#if TLS}}{{PORT}}{{else}}{{INSECURE_PORT}}{{/if}}"),
+            set(&["TLS", "PORT", "INSECURE_PORT"])
+        );
+    }
+
+    #[test]
+    fn paths_refer_to_inputs_by_their_first_segment() {
+        assert_eq!(
+            variables("{{this.USER}} {{./HOST}} {{@root.PORT}} {{CONFIG.db.name}}"),
+            set(&["USER", "HOST", "PORT", "CONFIG"])
+        );
+        assert_eq!(variables("{{this}} {{../PARENT}} {{@index}}"), set(&[]));
+    }
+
+    #[test]
+    fn parameters_of_helpers_are_variables() {
+        assert_eq!(
+            variables("{{#if (eq MODE \"tls\")}}{{lookup HOSTS INDEX}}{{/if}}"),
+            set(&["MODE", "HOSTS", "INDEX"])
+        );
+        assert_eq!(variables("{{len (concat USER)}}"), set(&["USER"]));
+    }
+
+    #[test]
+    fn undeclared_and_unused_inputs_are_reported() {
+        let spec = ConfigSetSpec {
+            inputs: vec![InputWithName {
+                name: "PASSWORD".to_string(),
+                from: Input {
+                    kind: Kinds::Secret,
+                    name: "database".to_string(),
+                    key: "PASSWORD".to_string(),
+                },
+            }],
+            templates: vec![Templates {
+                name: "URL".to_string(),
+                template: "{{USER}}@db".to_string(),
+                target: "app".to_string(),
+            }],
+            ..Default::default()
+        };
+        let rules: Vec<&str> = check_variables(&spec)
+            .into_iter()
+            .map(|(rule, _, _)| rule)
+            .collect();
+        assert_eq!(rules, vec!["undeclared-variable", "unused-input"]);
+    }
+
+    #[test]
+    fn patterns_that_do_not_compile_are_reported() {
+        let schema = JSONSchemaProps {
+            type_: Some("string".to_string()),
+            pattern: Some("^(?!kube-).*$".to_string()),
+            ..Default::default()
+        };
+        let mut problems = vec![];
+        check_schema(&schema, &json!("app"), ".name", &mut problems);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("can't be checked"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn schema_problems_have_paths() {
+        let mut problems = vec![];
+        let document: Value =
+            serde_yaml::from_str(include_str!("../../tests/lint/schema.yaml")).unwrap();
+        let crd = ConfigSet::crd();
+        let schema = crd_schema(&crd)
+            .and_then(|schema| schema.properties.as_ref()?.get("spec"))
+            .unwrap();
+        check_schema(schema, &document["spec"], ".spec", &mut problems);
+        assert_eq!(
+            problems,
+            vec![".spec.targets[0].target.kind: must be one of \"Secret\", \"ConfigMap\""]
+        );
+    }
+}
@@ -1,6 +1,32 @@
+//! Shoebill builds Secrets and ConfigMaps from templates that use values
+//! of other Secrets and ConfigMaps. The crate is used by the `shoebill` binary,
+//! but the reconciliation logic can also be embedded into other operators:
+//! types of the API are in [`api`], templates are rendered by [`render_templates`]
+//! and the controller is started by [`run_controller`]
+use kube::runtime::finalizer;
+use std::str::Utf8Error;
 use thiserror::Error;
 
+pub mod api;
+pub mod controllers;
+pub mod health;
+pub mod helpers;
+pub mod logging;
+pub mod metrics;
+pub mod render;
+pub mod telemetry;
+pub mod webhooks;
+
+pub use api::v1alpha1::configsets_api::{
+    ConfigSet, ConfigSetSpec, ConfigSetStatus, Input, InputWithName, Kinds, Target, TargetWithName,
+    Templates,
+};
+pub use controllers::{run_controller, ControllerConfig, LeaseConfig};
+pub use render::render_templates;
+
+/// Errors of the reconciliation, shared by all the controllers
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("SerializationError: {0}")]
     SerializationError(#[source] serde_json::Error),
@@ -13,12 +39,143 @@ pub enum Error {
     // so boxing this error to break cycles
     FinalizerError(#[source] Box<kube::runtime::finalizer::Error<Error>>),
 
-    #[error("IllegalDocument")]
-    IllegalDocument,
+    #[error("IllegalConfigSet: {0}")]
+    IllegalConfigSet(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("input {kind} {name} is not found")]
+    InputNotFound { kind: String, name: String },
+
+    #[error("key {key} is not set in {kind} {name}")]
+    KeyNotFound {
+        kind: String,
+        name: String,
+        key: String,
+    },
+
+    #[error("value of the key {key} in {kind} {name} is not valid UTF-8: {source}")]
+    InvalidUtf8 {
+        kind: String,
+        name: String,
+        key: String,
+        #[source]
+        source: Utf8Error,
+    },
+
+    #[error("template {template} can't be rendered: {source}")]
+    TemplateRender {
+        template: String,
+        #[source]
+        source: Box<handlebars::RenderError>,
+    },
+
+    #[error("target {0} is missing")]
+    TargetMissing(String),
+
+    #[error("{0} is not set in the object metadata")]
+    MissingMetadata(String),
+
+    #[error("the lease {0} is lost")]
+    LeadershipLost(String),
+
+    #[error("lease settings are invalid: {0}")]
+    InvalidLeaseConfig(String),
+
+    #[error("Telemetry Error: {0}")]
+    TelemetryError(String),
+
+    #[error("{path} is not valid: {reason}")]
+    InvalidFile { path: String, reason: String },
+
+    #[error("{path} can't be written: {reason}")]
+    OutputError { path: String, reason: String },
 }
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
 impl Error {
+    /// Short reason that is shown in the ConfigSet status
+    pub fn reason(&self) -> String {
+        match self {
+            Error::SerializationError(_) => "SerializationError".to_string(),
+            Error::KubeError(_) => "KubeError".to_string(),
+            Error::FinalizerError(_) => "FinalizerError".to_string(),
+            Error::IllegalConfigSet(_) => "IllegalConfigSet".to_string(),
+            Error::InputNotFound { .. } => "InputNotFound".to_string(),
+            Error::KeyNotFound { .. } => "KeyNotFound".to_string(),
+            Error::InvalidUtf8 { .. } => "InvalidUtf8".to_string(),
+            Error::TemplateRender { .. } => "TemplateRender".to_string(),
+            Error::TargetMissing(_) => "TargetMissing".to_string(),
+            Error::MissingMetadata(_) => "MissingMetadata".to_string(),
+            Error::LeadershipLost(_) => "LeadershipLost".to_string(),
+            Error::InvalidLeaseConfig(_) => "InvalidLeaseConfig".to_string(),
+            Error::TelemetryError(_) => "TelemetryError".to_string(),
+            Error::InvalidFile { .. } => "InvalidFile".to_string(),
+            Error::OutputError { .. } => "OutputError".to_string(),
+        }
+    }
+
+    /// Transient errors are retried with backoff, other ones
+    /// can't be fixed without changing the spec.
+    /// Inputs may be fixed without touching the ConfigSet, so they are retried
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::KubeError(_)
+            | Error::InputNotFound { .. }
+            | Error::KeyNotFound { .. }
+            | Error::InvalidUtf8 { .. } => true,
+            Error::SerializationError(_)
+            | Error::IllegalConfigSet(_)
+            | Error::TemplateRender { .. }
+            | Error::TargetMissing(_)
+            | Error::MissingMetadata(_)
+            | Error::LeadershipLost(_)
+            | Error::InvalidLeaseConfig(_)
+            | Error::TelemetryError(_)
+            | Error::InvalidFile { .. }
+            | Error::OutputError { .. } => false,
+            Error::FinalizerError(err) => match err.as_ref() {
+                finalizer::Error::ApplyFailed(err) | finalizer::Error::CleanupFailed(err) => {
+                    err.is_transient()
+                }
+                finalizer::Error::AddFinalizer(_) | finalizer::Error::RemoveFinalizer(_) => true,
+                finalizer::Error::UnnamedObject => false,
+            },
+        }
+    }
+
+    /// Label that is used for the error in metrics
     pub fn metric_label(&self) -> String {
-        format!("{self:?}").to_lowercase()
+        self.reason().to_lowercase()
+    }
+
+    /// The error with values of the current reconciliation replaced in messages.
+    /// Errors leave the reconciliation, and they are handled by the controller
+    /// where values are not known anymore, so they are redacted before that.
+    /// Causes of rendering errors are dropped, because they can't be rebuilt
+    pub fn redacted(self) -> Error {
+        match self {
+            Error::TemplateRender { template, source } => {
+                let mut redacted = handlebars::RenderError::new(logging::redact(&source.desc));
+                redacted.template_name = source.template_name.clone();
+                redacted.line_no = source.line_no;
+                redacted.column_no = source.column_no;
+                Error::TemplateRender {
+                    template,
+                    source: Box::new(redacted),
+                }
+            }
+            Error::KubeError(kube::Error::Api(mut response)) => {
+                response.message = logging::redact(&response.message);
+                Error::KubeError(kube::Error::Api(response))
+            }
+            Error::FinalizerError(err) => Error::FinalizerError(Box::new(match *err {
+                finalizer::Error::ApplyFailed(err) => finalizer::Error::ApplyFailed(err.redacted()),
+                finalizer::Error::CleanupFailed(err) => {
+                    finalizer::Error::CleanupFailed(err.redacted())
+                }
+                err => err,
+            })),
+            err => err,
+        }
     }
 }
@@ -0,0 +1,270 @@
+//! Logging of the controller. Lines are written either as text or as JSON,
+//! and in both formats values of inputs and rendered templates are replaced
+//! with a placeholder, so they never get to logs, even when they are a part
+//! of an error message
+use crate::telemetry;
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+static REDACTED: &str = "[REDACTED]";
+
+tokio::task_local! {
+    static CONTEXT: LogContext;
+}
+
+/// Format of log lines
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines, like the default ones of env_logger
+    Text,
+    /// A JSON object per line, with fields of the reconciliation
+    Json,
+}
+
+/// Fields of the reconciliation that are added to every log line written by it
+#[derive(Clone, Debug, Default)]
+pub struct LogContext {
+    configset: String,
+    namespace: Option<String>,
+    target: Option<String>,
+    reconcile_id: String,
+    // Shared by nested contexts, so values found while writing targets are redacted everywhere
+    sensitive: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl LogContext {
+    /// A context of a single reconciliation, it gets a random id
+    pub fn new(configset: String, namespace: Option<String>) -> Self {
+        LogContext {
+            configset,
+            namespace,
+            reconcile_id: format!("{:016x}", rand::random::<u64>()),
+            ..Default::default()
+        }
+    }
+
+    pub fn reconcile_id(&self) -> &str {
+        &self.reconcile_id
+    }
+}
+
+/// Initialize the logger, the level is still set by RUST_LOG
+pub fn init(format: LogFormat) {
+    env_logger::Builder::from_default_env()
+        .format(move |buf, record| writeln!(buf, "{}", format_line(format, record)))
+        .init();
+}
+
+// A log line without the trailing newline
+fn format_line(format: LogFormat, record: &log::Record) -> String {
+    let message = redact(&record.args().to_string());
+    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let context = CONTEXT.try_with(|context| context.clone()).ok();
+    let trace_id = telemetry::trace_id();
+    match format {
+        LogFormat::Text => format!(
+            "[{} {:<5} {}{}] {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            trace_id
+                .map(|trace_id| format!(" trace_id={}", trace_id))
+                .unwrap_or_default(),
+            message
+        ),
+        LogFormat::Json => {
+            let mut line = Map::new();
+            line.insert("timestamp".to_string(), json!(timestamp));
+            line.insert("level".to_string(), json!(record.level().as_str()));
+            line.insert("module".to_string(), json!(record.target()));
+            line.insert("message".to_string(), json!(message));
+            if let Some(trace_id) = trace_id {
+                line.insert("traceId".to_string(), json!(trace_id));
+            }
+            if let Some(context) = context {
+                line.insert("configset".to_string(), json!(context.configset));
+                line.insert("reconcileId".to_string(), json!(context.reconcile_id));
+                if let Some(namespace) = context.namespace {
+                    line.insert("namespace".to_string(), json!(namespace));
+                }
+                if let Some(target) = context.target {
+                    line.insert("target".to_string(), json!(target));
+                }
+            }
+            Value::Object(line).to_string()
+        }
+    }
+}
+
+/// Run the future with the context, it's used by all the log lines written by it
+pub async fn scope<F: Future>(context: LogContext, f: F) -> F::Output {
+    CONTEXT.scope(context, f).await
+}
+
+/// Run the future with the target set in the current context
+pub(crate) async fn with_target<F: Future>(target: String, f: F) -> F::Output {
+    match CONTEXT.try_with(|context| context.clone()) {
+        Ok(mut context) => {
+            context.target = Some(target);
+            CONTEXT.scope(context, f).await
+        }
+        Err(_) => f.await,
+    }
+}
+
+/// Values that must never be logged by the current reconciliation
+pub(crate) fn add_sensitive<'a>(values: impl IntoIterator<Item = &'a String>) {
+    let _ = CONTEXT.try_with(|context| {
+        let mut sensitive = context.sensitive.lock().unwrap();
+        sensitive.extend(
+            values
+                .into_iter()
+                .filter(|value| !value.is_empty())
+                .cloned(),
+        );
+    });
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Occurrences that are a part of a longer word are kept, so short values
+// like "1" or "on" don't mangle numbers and words of every log line
+fn redact_value(message: &str, value: &str) -> String {
+    let glued = |edge: Option<char>, neighbour: Option<char>| matches!((edge, neighbour), (Some(edge), Some(neighbour)) if is_word_char(edge) && is_word_char(neighbour));
+    let mut redacted = String::with_capacity(message.len());
+    let mut rest = 0;
+    for (start, _) in message.match_indices(value) {
+        let end = start + value.len();
+        if glued(value.chars().next(), message[..start].chars().next_back())
+            || glued(value.chars().next_back(), message[end..].chars().next())
+        {
+            continue;
+        }
+        redacted.push_str(&message[rest..start]);
+        redacted.push_str(REDACTED);
+        rest = end;
+    }
+    redacted.push_str(&message[rest..]);
+    redacted
+}
+
+/// Replace sensitive values of the current reconciliation in the message.
+/// It's used for log lines, and for events and statuses, that may contain
+/// values in error messages
+pub fn redact(message: &str) -> String {
+    CONTEXT
+        .try_with(|context| {
+            let sensitive = context.sensitive.lock().unwrap();
+            // Longer values first, so values containing other ones are fully redacted
+            let mut values: Vec<&String> = sensitive.iter().collect();
+            values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+            values
+                .into_iter()
+                .fold(message.to_string(), |message, value| {
+                    redact_value(&message, value)
+                })
+        })
+        .unwrap_or_else(|_| message.to_string())
+}
+
+/// A logger that keeps formatted lines in memory, so tests can check what gets
+/// to logs. It's shared by all the tests of the binary, so lines of other tests
+/// are captured too
+#[cfg(test)]
+pub(crate) mod capture {
+    use super::*;
+
+    static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            // Formatting may log too, so lines are formatted before the lock is taken
+            let text = format_line(LogFormat::Text, record);
+            let json = format_line(LogFormat::Json, record);
+            LINES.lock().unwrap().extend([text, json]);
+        }
+
+        fn flush(&self) {}
+    }
+
+    pub(crate) fn install() {
+        // Only the first call sets the logger
+        let _ = log::set_logger(&Capture);
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+
+    pub(crate) fn lines() -> Vec<String> {
+        LINES.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, Record};
+
+    static INPUT: &str = "s3cr3t-password";
+    static RENDERED: &str = "postgres://app:s3cr3t-password@db:5432/app";
+
+    // Formats an error that contains both the input and the rendered value
+    fn line_with_values(format: LogFormat) -> String {
+        let context = LogContext::new("app".to_string(), Some("default".to_string()));
+        CONTEXT.sync_scope(context, || {
+            add_sensitive([&INPUT.to_string(), &RENDERED.to_string()]);
+            format_line(
+                format,
+                &Record::builder()
+                    .args(format_args!("can't write {} (from {})", RENDERED, INPUT))
+                    .level(Level::Error)
+                    .target("controller")
+                    .build(),
+            )
+        })
+    }
+
+    #[test]
+    fn text_lines_are_redacted() {
+        let line = line_with_values(LogFormat::Text);
+        assert!(!line.contains(INPUT), "{}", line);
+        assert!(!line.contains(RENDERED), "{}", line);
+        assert!(line.ends_with("can't write [REDACTED] (from [REDACTED])"));
+    }
+
+    #[test]
+    fn json_lines_are_redacted() {
+        let line = line_with_values(LogFormat::Json);
+        assert!(!line.contains(INPUT), "{}", line);
+        assert!(!line.contains(RENDERED), "{}", line);
+        let line: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            line["message"],
+            json!("can't write [REDACTED] (from [REDACTED])")
+        );
+        assert_eq!(line["configset"], json!("app"));
+    }
+
+    #[test]
+    fn short_values_are_redacted_as_words() {
+        let context = LogContext::new("app".to_string(), None);
+        let message = CONTEXT.sync_scope(context, || {
+            add_sensitive([&"1".to_string(), &"on".to_string()]);
+            redact("retry 1 of 10 is on the way, connection lost")
+        });
+        assert_eq!(
+            message,
+            "retry [REDACTED] of 10 is [REDACTED] the way, connection lost"
+        );
+    }
+}
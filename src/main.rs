@@ -1,55 +1,199 @@
-#![allow(unused_imports, unused_variables)]
 use std::process::exit;
 
-use actix_web::{
-    get, middleware, web::Data, App, HttpRequest, HttpResponse, HttpServer, Responder,
-};
-use clap::{Args, Command, Parser, Subcommand};
+use actix_web::{get, web::Data, App, HttpResponse, HttpServer, Responder};
+use clap::Parser;
 use cmd::{Cli, Commands};
-use controllers::configsets_controller;
+use controller::health::Health;
+use controller::metrics::Metrics;
+use controller::{
+    helpers, logging, run_controller, telemetry, webhooks, ControllerConfig, LeaseConfig,
+};
 use log::*;
-mod api;
+use std::sync::Arc;
+use std::time::Duration;
 mod cmd;
-mod controllers;
-mod helpers;
 
 #[get("/")]
-async fn index(req: HttpRequest) -> impl Responder {
+async fn index() -> impl Responder {
     let d = "Shoebill";
-    HttpResponse::Ok().json(&d)
+    HttpResponse::Ok().json(d)
+}
+
+#[get("/metrics")]
+async fn metrics_endpoint(metrics: Data<Metrics>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
+
+// Liveness, fails only when the controller can't recover without a restart
+#[get("/healthz")]
+async fn healthz(health: Data<Health>) -> impl Responder {
+    let problems = health.liveness();
+    if problems.is_empty() {
+        HttpResponse::Ok().json("ok")
+    } else {
+        HttpResponse::ServiceUnavailable().json(problems)
+    }
+}
+
+// Readiness, fails until controllers are connected and synced
+#[get("/readyz")]
+async fn readyz(health: Data<Health>) -> impl Responder {
+    let problems = health.readiness();
+    if problems.is_empty() {
+        HttpResponse::Ok().json("ok")
+    } else {
+        HttpResponse::ServiceUnavailable().json(problems)
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
     let cli = Cli::parse();
+    logging::init(cli.log_format);
 
     match &cli.command {
-        Commands::Manifests(args) => helpers::manifests::generate_kube_manifests(
-            args.namespace.clone(),
-            args.image.clone(),
-            args.tag.clone(),
-        ),
+        Commands::Manifests(args) => {
+            let config = helpers::manifests::ManifestsConfig {
+                namespace: args.namespace.clone(),
+                image: args.image.clone(),
+                tag: args.tag.clone(),
+                webhook: args.webhook,
+                conversion_webhook: args.conversion_webhook,
+                replicas: args.replicas,
+                watch_namespaces: args.watch_namespaces.clone(),
+                configset_selector: args.configset_selector.clone(),
+                log_level: args.log_level.clone(),
+                log_format: args.controller_log_format,
+                requests_cpu: args.requests_cpu.clone(),
+                requests_memory: args.requests_memory.clone(),
+                limits_cpu: args.limits_cpu.clone(),
+                limits_memory: Some(args.limits_memory.clone()),
+                service_monitor: args.service_monitor,
+                controller_args: args.controller_args.clone(),
+            };
+            if let Err(err) =
+                helpers::manifests::generate_kube_manifests(&config, args.format, &args.output)
+            {
+                error!("{}", err);
+                exit(1)
+            }
+        }
+        Commands::Render(args) => {
+            if let Err(err) = helpers::render::render(&args.file, &args.input, args.mask_secrets) {
+                error!("{}", err);
+                exit(1)
+            }
+        }
+        Commands::Validate(args) => match helpers::validate::validate(&args.files, args.format) {
+            Ok(true) => {}
+            Ok(false) => exit(1),
+            Err(err) => {
+                error!("{}", err);
+                exit(1)
+            }
+        },
+        // Like diff(1), 1 means that there are changes and 2 that there is an error
+        Commands::Diff(args) => {
+            match helpers::diff::diff(&args.file, args.namespace.clone(), args.show_secrets).await {
+                Ok(false) => {}
+                Ok(true) => exit(1),
+                Err(err) => {
+                    error!("{}", err);
+                    exit(2)
+                }
+            }
+        }
         Commands::Controller(args) => {
+            if let Some(endpoint) = &args.otlp_endpoint {
+                if let Err(err) = telemetry::init(endpoint) {
+                    error!("{}", err);
+                    exit(1)
+                }
+            }
             // Initiatilize Kubernetes controller state
-            let controller = configsets_controller::setup();
+            let metrics = Arc::new(Metrics::new());
+            let health = Arc::new(Health::new());
+            let config = ControllerConfig {
+                namespaces: args.watch_namespaces.clone(),
+                selector: args.configset_selector.clone(),
+                cache_selector: args.cache_selector.clone(),
+                leader_election: args.leader_election.then(|| LeaseConfig {
+                    name: args.lease_name.clone(),
+                    namespace: args.lease_namespace.clone(),
+                    identity: args.lease_identity.clone(),
+                    duration: Duration::from_secs(args.lease_duration),
+                    renew_deadline: Duration::from_secs(args.lease_renew_deadline),
+                }),
+                min_backoff: Duration::from_secs(args.min_backoff),
+                max_backoff: Duration::from_secs(args.max_backoff),
+                max_concurrent_reconciles: args.max_concurrent_reconciles,
+                resync_interval: args.resync_interval.map(Duration::from_secs),
+                dry_run: args.dry_run,
+                metrics: metrics.clone(),
+                health: health.clone(),
+            };
+            let controllers = async {
+                if let Err(err) = run_controller(config).await {
+                    error!("{}", err);
+                    exit(1)
+                }
+            };
             // Start web server
-            let server =
-                match HttpServer::new(move || App::new().service(index)).bind("0.0.0.0:8080") {
-                    Ok(server) => server.shutdown_timeout(5),
-                    Err(err) => {
-                        error!("{}", err);
-                        exit(1)
+            let metrics_data = Data::from(metrics);
+            let health_data = Data::from(health);
+            let server = match HttpServer::new(move || {
+                App::new()
+                    .app_data(metrics_data.clone())
+                    .app_data(health_data.clone())
+                    .service(index)
+                    .service(healthz)
+                    .service(readyz)
+                    .service(metrics_endpoint)
+                    .service(webhooks::configsets_webhook::validate)
+                    .service(webhooks::configsets_webhook::convert)
+                    .service(webhooks::configsets_webhook::validate_cluster)
+            })
+            .bind("0.0.0.0:8080")
+            {
+                Ok(server) => server.shutdown_timeout(5),
+                Err(err) => {
+                    error!("{}", err);
+                    exit(1)
+                }
+            };
+            // Webhooks must be served over HTTPS
+            let server = match (&args.webhook_cert, &args.webhook_key) {
+                (Some(cert), Some(key)) => {
+                    let tls_config = match webhooks::load_tls_config(cert, key) {
+                        Ok(config) => config,
+                        Err(err) => {
+                            error!("{}", err);
+                            exit(1)
+                        }
+                    };
+                    match server
+                        .bind_rustls_021(format!("0.0.0.0:{}", args.webhook_port), tls_config)
+                    {
+                        Ok(server) => server,
+                        Err(err) => {
+                            error!("{}", err);
+                            exit(1)
+                        }
                     }
-                };
+                }
+                _ => server,
+            };
             // Both runtimes implements graceful shutdown, so poll until both are done
-            match tokio::join!(controller, server.run()).1 {
-                Ok(res) => info!("server is started"),
+            match tokio::join!(controllers, server.run()).1 {
+                Ok(_) => info!("server is started"),
                 Err(err) => {
                     error!("{}", err);
                     exit(1)
                 }
             };
+            telemetry::shutdown();
         }
     }
 
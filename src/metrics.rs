@@ -0,0 +1,199 @@
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec,
+    Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Metrics of the controller, exported in the Prometheus format on /metrics.
+/// Every metric has the `kind` label, so ConfigSets and ClusterConfigSets
+/// can be told apart
+pub struct Metrics {
+    registry: Registry,
+    reconciliations: IntCounterVec,
+    failures: IntCounterVec,
+    reconcile_duration: HistogramVec,
+    managed_objects: IntGaugeVec,
+    managed_targets: IntGaugeVec,
+    input_failures: IntCounterVec,
+    template_failures: IntCounterVec,
+    cache_misses: IntCounterVec,
+    // Number of targets per object, gauges are summed from it
+    targets: Mutex<HashMap<String, HashMap<String, i64>>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let reconciliations = IntCounterVec::new(
+            opts!(
+                "shoebill_reconciliations_total",
+                "Number of reconciliations by result"
+            ),
+            &["kind", "result"],
+        )
+        .unwrap();
+        let failures = IntCounterVec::new(
+            opts!(
+                "shoebill_reconciliation_errors_total",
+                "Number of failed reconciliations by error"
+            ),
+            &["kind", "error"],
+        )
+        .unwrap();
+        let reconcile_duration = HistogramVec::new(
+            histogram_opts!(
+                "shoebill_reconcile_duration_seconds",
+                "Duration of reconciliations",
+                vec![0.01, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.]
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let managed_objects = IntGaugeVec::new(
+            opts!(
+                "shoebill_managed_objects",
+                "Number of ConfigSets and ClusterConfigSets managed by the controller"
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let managed_targets = IntGaugeVec::new(
+            opts!(
+                "shoebill_managed_targets",
+                "Number of Secrets and ConfigMaps managed by the controller"
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let input_failures = IntCounterVec::new(
+            opts!(
+                "shoebill_input_failures_total",
+                "Number of times inputs couldn't be resolved"
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let template_failures = IntCounterVec::new(
+            opts!(
+                "shoebill_template_failures_total",
+                "Number of times templates couldn't be rendered"
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let cache_misses = IntCounterVec::new(
+            opts!(
+                "shoebill_cache_misses_total",
+                "Number of Secrets and ConfigMaps that were read from the API instead of the cache"
+            ),
+            &["resource"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry
+            .register(Box::new(reconciliations.clone()))
+            .unwrap();
+        registry.register(Box::new(failures.clone())).unwrap();
+        registry
+            .register(Box::new(reconcile_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(managed_objects.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(managed_targets.clone()))
+            .unwrap();
+        registry.register(Box::new(input_failures.clone())).unwrap();
+        registry
+            .register(Box::new(template_failures.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_misses.clone())).unwrap();
+
+        Metrics {
+            registry,
+            reconciliations,
+            failures,
+            reconcile_duration,
+            managed_objects,
+            managed_targets,
+            input_failures,
+            template_failures,
+            cache_misses,
+            targets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start measuring the reconciliation, it's observed when the timer is dropped
+    pub(crate) fn reconcile_timer(&self, kind: &str) -> HistogramTimer {
+        self.reconcile_duration
+            .with_label_values(&[kind])
+            .start_timer()
+    }
+
+    pub(crate) fn reconcile_success(&self, kind: &str) {
+        self.reconciliations
+            .with_label_values(&[kind, "success"])
+            .inc();
+    }
+
+    pub(crate) fn reconcile_failure(&self, kind: &str, error: &str) {
+        self.reconciliations
+            .with_label_values(&[kind, "failure"])
+            .inc();
+        self.failures.with_label_values(&[kind, error]).inc();
+    }
+
+    pub(crate) fn input_failure(&self, kind: &str) {
+        self.input_failures.with_label_values(&[kind]).inc();
+    }
+
+    pub(crate) fn template_failure(&self, kind: &str) {
+        self.template_failures.with_label_values(&[kind]).inc();
+    }
+
+    pub(crate) fn cache_miss(&self, resource: &str) {
+        self.cache_misses.with_label_values(&[resource]).inc();
+    }
+
+    /// Set the number of targets managed by the object
+    pub(crate) fn set_targets(&self, kind: &str, object: String, targets: i64) {
+        let mut all = self.targets.lock().unwrap();
+        let objects = all.entry(kind.to_string()).or_default();
+        objects.insert(object, targets);
+        self.update_gauges(kind, objects);
+    }
+
+    /// Forget the object when it's deleted
+    pub(crate) fn remove_object(&self, kind: &str, object: &str) {
+        let mut all = self.targets.lock().unwrap();
+        let objects = all.entry(kind.to_string()).or_default();
+        objects.remove(object);
+        self.update_gauges(kind, objects);
+    }
+
+    fn update_gauges(&self, kind: &str, objects: &HashMap<String, i64>) {
+        self.managed_objects
+            .with_label_values(&[kind])
+            .set(objects.len() as i64);
+        self.managed_targets
+            .with_label_values(&[kind])
+            .set(objects.values().sum());
+    }
+
+    /// Encode all the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        let encoder = TextEncoder::new();
+        encoder
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
@@ -0,0 +1,63 @@
+use crate::api::v1alpha1::configsets_api::{Kinds, TargetWithName, Templates};
+use crate::{logging, Error, Result};
+use handlebars::Handlebars;
+use log::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Values rendered for a single target of a ConfigSet
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderedTarget {
+    /// Kind of the target object
+    pub kind: Kinds,
+    /// Name of the target Secret or ConfigMap
+    pub name: String,
+    /// Rendered templates by their keys
+    pub data: BTreeMap<String, String>,
+}
+
+/// Render templates with values of inputs, without talking to the cluster.
+/// The result contains every target by its name in the spec,
+/// targets without templates have no data
+pub fn render_templates(
+    templates: &[Templates],
+    targets: &[TargetWithName],
+    inputs: &HashMap<String, String>,
+) -> Result<BTreeMap<String, RenderedTarget>> {
+    let mut rendered: BTreeMap<String, RenderedTarget> = targets
+        .iter()
+        .map(|target| {
+            (
+                target.name.clone(),
+                RenderedTarget {
+                    kind: target.target.kind.clone(),
+                    name: target.target.name.clone(),
+                    data: BTreeMap::new(),
+                },
+            )
+        })
+        .collect();
+
+    let reg = Handlebars::new();
+    for template in templates {
+        info!("building template {}", template.name);
+        let value = match reg.render_template(template.template.as_str(), inputs) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(Error::TemplateRender {
+                    template: template.name.clone(),
+                    source: Box::new(err),
+                })
+            }
+        };
+        match rendered.get_mut(&template.target) {
+            Some(target) => {
+                target.data.insert(template.name.clone(), value);
+            }
+            None => return Err(Error::TargetMissing(template.target.clone())),
+        }
+    }
+    for target in rendered.values() {
+        logging::add_sensitive(target.data.values());
+    }
+    Ok(rendered)
+}
@@ -0,0 +1,139 @@
+//! Tracing of reconciliations. Spans are exported with OTLP, when the endpoint
+//! is set, otherwise they are not recorded at all. Trace ids are added to
+//! log lines and Events, so they can be found by the trace and vice versa
+use crate::{Error, Result};
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+
+/// A layer that records spans, and the provider that exports them to the OTLP
+/// collector. The endpoint is a gRPC one, like http://localhost:4317.
+/// Nothing is installed globally, spans are flushed by the provider
+pub fn layer<S>(endpoint: &str) -> Result<(OpenTelemetryLayer<S, Tracer>, TracerProvider)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint)
+        .build_span_exporter()
+        .map_err(|err| Error::TelemetryError(err.to_string()))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "shoebill",
+            )])),
+        )
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("shoebill"));
+    Ok((layer, provider))
+}
+
+/// Export spans of the whole process to the OTLP collector
+pub fn init(endpoint: &str) -> Result<()> {
+    let (layer, provider) = layer(endpoint)?;
+    // The global provider is flushed by shutdown
+    opentelemetry::global::set_tracer_provider(provider);
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .map_err(|err| Error::TelemetryError(err.to_string()))
+}
+
+/// Export spans that are not exported yet
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Id of the trace of the current span, if it's recorded
+pub(crate) fn trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+        TraceService, TraceServiceServer,
+    };
+    use opentelemetry_proto::tonic::collector::trace::v1::{
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    // An OTLP collector that passes exported requests to the test
+    struct Receiver(mpsc::UnboundedSender<ExportTraceServiceRequest>);
+
+    #[tonic::async_trait]
+    impl TraceService for Receiver {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            let _ = self.0.send(request.into_inner());
+            Ok(tonic::Response::new(ExportTraceServiceResponse {
+                partial_success: None,
+            }))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_with_their_trace_ids() {
+        let (sender, mut exported) = mpsc::unbounded_channel();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(Receiver(sender)))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        // The subscriber is only used by the test, other tests keep their own
+        let (layer, provider) = layer(&endpoint).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        let logged_trace_id = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("reconcile").in_scope(trace_id)
+        })
+        .unwrap();
+        // Flushing blocks until spans are sent
+        tokio::task::spawn_blocking(move || {
+            provider.force_flush();
+            drop(provider);
+        })
+        .await
+        .unwrap();
+
+        let request = tokio::time::timeout(Duration::from_secs(10), exported.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let spans: Vec<_> = request
+            .resource_spans
+            .iter()
+            .flat_map(|resource| resource.scope_spans.iter())
+            .flat_map(|scope| scope.spans.iter())
+            .collect();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "reconcile");
+        let exported_trace_id: String = spans[0]
+            .trace_id
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(exported_trace_id, logged_trace_id);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::api::v1alpha1::clusterconfigsets_api::ClusterConfigSet;
+use crate::api::v1alpha1::configsets_api as v1alpha1;
+use crate::api::v1beta1::configsets_api as v1beta1;
+use actix_web::{post, web::Json, HttpResponse, Responder};
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview};
+use kube::core::conversion::{ConversionRequest, ConversionResponse, ConversionReview};
+use kube::core::{DynamicObject, Status};
+use kube::Resource;
+use log::*;
+use serde_json::Value;
+
+/// Only changes of the spec are validated. Objects that are being deleted and updates
+/// that keep the spec, like removing the finalizer, are allowed, otherwise objects
+/// created before validation rules got stricter could never be deleted
+fn spec_changed<K: Resource, S: PartialEq>(
+    object: &K,
+    old_object: Option<&K>,
+    spec: impl Fn(&K) -> &S,
+) -> bool {
+    if object.meta().deletion_timestamp.is_some() {
+        return false;
+    }
+    match old_object {
+        Some(old_object) => spec(old_object) != spec(object),
+        None => true,
+    }
+}
+
+/// Validating admission webhook for ConfigSets.
+/// It runs the same validation as the reconciler, so ConfigSets
+/// that would fail to reconcile are rejected on apply
+#[post("/validate/configsets")]
+pub(crate) async fn validate(review: Json<AdmissionReview<v1alpha1::ConfigSet>>) -> impl Responder {
+    let req: AdmissionRequest<v1alpha1::ConfigSet> = match review.into_inner().try_into() {
+        Ok(req) => req,
+        Err(err) => {
+            error!("invalid admission request: {}", err);
+            return HttpResponse::BadRequest()
+                .json(AdmissionResponse::invalid(err.to_string()).into_review());
+        }
+    };
+
+    let mut res = AdmissionResponse::from(&req);
+    if let Some(obj) = &req.object {
+        if !spec_changed(obj, req.old_object.as_ref(), |confset| &confset.spec) {
+            debug!("spec of configset {} is not changed", req.name);
+        } else if let Err(err) = obj.spec.validate() {
+            info!(
+                "rejecting configset {}/{}: {}",
+                req.namespace.clone().unwrap_or_default(),
+                req.name,
+                err
+            );
+            res = res.deny(err.to_string());
+        }
+    }
+    let review: AdmissionReview<DynamicObject> = res.into_review();
+    HttpResponse::Ok().json(review)
+}
+
+/// Validating admission webhook for ClusterConfigSets
+#[post("/validate/clusterconfigsets")]
+pub(crate) async fn validate_cluster(
+    review: Json<AdmissionReview<ClusterConfigSet>>,
+) -> impl Responder {
+    let req: AdmissionRequest<ClusterConfigSet> = match review.into_inner().try_into() {
+        Ok(req) => req,
+        Err(err) => {
+            error!("invalid admission request: {}", err);
+            return HttpResponse::BadRequest()
+                .json(AdmissionResponse::invalid(err.to_string()).into_review());
+        }
+    };
+
+    let mut res = AdmissionResponse::from(&req);
+    if let Some(obj) = &req.object {
+        if !spec_changed(obj, req.old_object.as_ref(), |cconfset| &cconfset.spec) {
+            debug!("spec of clusterconfigset {} is not changed", req.name);
+        } else if let Err(err) = obj.spec.validate() {
+            info!("rejecting clusterconfigset {}: {}", req.name, err);
+            res = res.deny(err.to_string());
+        }
+    }
+    let review: AdmissionReview<DynamicObject> = res.into_review();
+    HttpResponse::Ok().json(review)
+}
+
+/// Conversion webhook for ConfigSets, the API server is using it
+/// to serve ConfigSets in the version other than the stored one
+#[post("/convert/configsets")]
+pub(crate) async fn convert(review: Json<ConversionReview>) -> impl Responder {
+    let req: ConversionRequest = match ConversionRequest::from_review(review.into_inner()) {
+        Ok(req) => req,
+        Err(err) => {
+            error!("invalid conversion request: {}", err);
+            return HttpResponse::BadRequest().json(
+                ConversionResponse::invalid(Status::failure(&err.to_string(), "InvalidRequest"))
+                    .into_review(),
+            );
+        }
+    };
+
+    let desired_api_version = req.desired_api_version.clone();
+    let mut converted: Vec<Value> = vec![];
+    for object in req.objects.iter() {
+        match convert_configset(object.clone(), desired_api_version.as_str()) {
+            Ok(object) => converted.push(object),
+            Err(err) => {
+                error!("conversion has failed: {}", err);
+                return HttpResponse::Ok().json(
+                    ConversionResponse::for_request(req)
+                        .failure(Status::failure(&err, "ConversionFailed"))
+                        .into_review(),
+                );
+            }
+        }
+    }
+    HttpResponse::Ok().json(
+        ConversionResponse::for_request(req)
+            .success(converted)
+            .into_review(),
+    )
+}
+
+/// Convert a ConfigSet to the desired version
+pub(crate) fn convert_configset(object: Value, desired_api_version: &str) -> Result<Value, String> {
+    let api_version = object
+        .get("apiVersion")
+        .and_then(|v| v.as_str())
+        .ok_or("apiVersion is not set")?
+        .to_string();
+    if api_version == desired_api_version {
+        return Ok(object);
+    }
+
+    let alpha = v1alpha1::ConfigSet::api_version(&()).to_string();
+    let beta = v1beta1::ConfigSet::api_version(&()).to_string();
+    let result = if api_version == alpha && desired_api_version == beta {
+        serde_json::from_value::<v1alpha1::ConfigSet>(object)
+            .map(v1beta1::ConfigSet::from)
+            .and_then(serde_json::to_value)
+    } else if api_version == beta && desired_api_version == alpha {
+        serde_json::from_value::<v1beta1::ConfigSet>(object)
+            .map(v1alpha1::ConfigSet::from)
+            .and_then(serde_json::to_value)
+    } else {
+        return Err(format!(
+            "can't convert from {} to {}",
+            api_version, desired_api_version
+        ));
+    };
+    result.map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+
+    fn configset(template_target: &str) -> v1alpha1::ConfigSet {
+        v1alpha1::ConfigSet::new(
+            "test",
+            v1alpha1::ConfigSetSpec {
+                templates: vec![v1alpha1::Templates {
+                    name: "KEY".to_string(),
+                    template: "value".to_string(),
+                    target: template_target.to_string(),
+                }],
+                ..Default::default()
+            },
+        )
+    }
+
+    fn full_configset() -> v1alpha1::ConfigSet {
+        let mut confset = v1alpha1::ConfigSet::new(
+            "test",
+            v1alpha1::ConfigSetSpec {
+                inputs: vec![v1alpha1::InputWithName {
+                    name: "PASSWORD".to_string(),
+                    from: v1alpha1::Input {
+                        kind: v1alpha1::Kinds::Secret,
+                        name: "database".to_string(),
+                        key: "password".to_string(),
+                    },
+                }],
+                targets: vec![v1alpha1::TargetWithName {
+                    name: "app".to_string(),
+                    target: v1alpha1::Target {
+                        kind: v1alpha1::Kinds::ConfigMap,
+                        name: "app-config".to_string(),
+                    },
+                }],
+                templates: vec![v1alpha1::Templates {
+                    name: "URL".to_string(),
+                    template: "postgres://{{ PASSWORD }}@db".to_string(),
+                    target: "app".to_string(),
+                }],
+                refresh_interval: Some("10m".to_string()),
+            },
+        );
+        confset.metadata.namespace = Some("default".to_string());
+        confset.metadata.resource_version = Some("42".to_string());
+        confset.status = Some(v1alpha1::ConfigSetStatus {
+            ready: true,
+            reason: Some("Synced".to_string()),
+            message: Some("all targets are synced".to_string()),
+            targets: 1,
+            last_sync_time: Some(chrono::Utc::now()),
+            observed_generation: Some(3),
+            last_handled_reconcile_at: Some("2024-01-01T00:00:00Z".to_string()),
+            pending_changes: vec!["ConfigMap/app-config: added [URL]".to_string()],
+            conditions: vec![Condition {
+                type_: "Paused".to_string(),
+                status: "False".to_string(),
+                reason: "Active".to_string(),
+                message: String::new(),
+                last_transition_time: Time(chrono::Utc::now()),
+                observed_generation: Some(3),
+            }],
+        });
+        confset
+    }
+
+    #[test]
+    fn conversion_round_trip_is_lossless() {
+        let alpha = serde_json::to_value(full_configset()).unwrap();
+        let beta_version = v1beta1::ConfigSet::api_version(&()).to_string();
+        let alpha_version = v1alpha1::ConfigSet::api_version(&()).to_string();
+
+        let beta = convert_configset(alpha.clone(), &beta_version).unwrap();
+        assert_eq!(beta["apiVersion"], beta_version.as_str());
+        assert_eq!(beta["spec"]["refreshInterval"], "10m");
+        assert_eq!(beta["spec"]["templates"][0]["key"], "URL");
+        assert_eq!(
+            beta["spec"]["inputs"][0]["secretKeyRef"],
+            serde_json::json!({"name": "database", "key": "password"})
+        );
+        assert_eq!(
+            beta["spec"]["targets"][0]["configMap"]["name"],
+            "app-config"
+        );
+        assert_eq!(beta["status"], alpha["status"]);
+
+        let back = convert_configset(beta, &alpha_version).unwrap();
+        assert_eq!(back, alpha);
+    }
+
+    #[test]
+    fn conversion_to_unknown_versions_fails() {
+        let alpha = serde_json::to_value(full_configset()).unwrap();
+        assert!(convert_configset(alpha, "shoebill.badhouseplants.net/v2").is_err());
+    }
+
+    #[test]
+    fn new_objects_are_validated() {
+        assert!(spec_changed(&configset("missing"), None, |c| &c.spec));
+    }
+
+    #[test]
+    fn changed_specs_are_validated() {
+        let old = configset("missing");
+        let new = configset("other");
+        assert!(spec_changed(&new, Some(&old), |c| &c.spec));
+    }
+
+    #[test]
+    fn unchanged_invalid_specs_are_allowed() {
+        let old = configset("missing");
+        assert!(old.spec.validate().is_err());
+        let mut new = old.clone();
+        new.metadata.finalizers = Some(vec![]);
+        assert!(!spec_changed(&new, Some(&old), |c| &c.spec));
+    }
+
+    #[test]
+    fn objects_being_deleted_are_allowed() {
+        let old = configset("missing");
+        let mut new = configset("other");
+        new.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+        assert!(!spec_changed(&new, Some(&old), |c| &c.spec));
+    }
+}
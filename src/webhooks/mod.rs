@@ -0,0 +1,32 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::anyhow;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::Item;
+
+pub mod configsets_webhook;
+
+/// Build the TLS config for serving webhooks, the API server
+/// doesn't talk to webhooks over plain HTTP
+pub fn load_tls_config(cert_path: &str, key_path: &str) -> anyhow::Result<ServerConfig> {
+    let certs: Vec<Certificate> =
+        rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no private key found in {}", key_path))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(config)
+}